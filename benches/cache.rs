@@ -0,0 +1,140 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+
+use rpcproxy::cache::RpcCache;
+use rpcproxy::jsonrpc::{JsonRpcRequest, JsonRpcResponse, SerializedResponse};
+
+fn request(json: &str) -> JsonRpcRequest {
+    serde_json::from_str(json).unwrap()
+}
+
+fn cache_key(c: &mut Criterion) {
+    let block_number =
+        request(r#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":1}"#);
+
+    let topics: Vec<String> = (0..4).map(|i| format!("0x{i:064x}")).collect();
+    let addresses: Vec<String> = (0..50).map(|i| format!("0x{i:040x}")).collect();
+    let get_logs = JsonRpcRequest {
+        jsonrpc: "2.0".to_string(),
+        method: "eth_getLogs".to_string(),
+        params: serde_json::json!([{
+            "fromBlock": "0x1000000",
+            "toBlock": "0x1001000",
+            "address": addresses,
+            "topics": [topics, null, topics],
+        }]),
+        id: serde_json::json!(1),
+    };
+
+    let eth_call = request(
+        r#"{"jsonrpc":"2.0","method":"eth_call","params":[{"from":"0x0000000000000000000000000000000000000001","to":"0x0000000000000000000000000000000000000002","data":"0x70a08231","gas":"0x5208"},"latest",{"0x0000000000000000000000000000000000000002":{"balance":"0x1","stateDiff":{"0x00":"0x01"}}}],"id":1}"#,
+    );
+
+    let mut group = c.benchmark_group("cache_key");
+    group.bench_function("eth_blockNumber", |b| {
+        b.iter(|| black_box(&block_number).cache_key())
+    });
+    group.bench_function("eth_getLogs_large_filter", |b| {
+        b.iter(|| black_box(&get_logs).cache_key())
+    });
+    group.bench_function("eth_call_nested_params", |b| {
+        b.iter(|| black_box(&eth_call).cache_key())
+    });
+    group.finish();
+}
+
+fn store(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let cache = RpcCache::new(10_000, 2000);
+    let response = Arc::new(JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        result: Some(serde_json::json!("0x1234")),
+        error: None,
+        id: serde_json::json!(1),
+    });
+    let keys: Vec<String> = (0..1000)
+        .map(|i| format!("eth_getBalance:[\"0x{i:x}\"]"))
+        .collect();
+
+    runtime.block_on(async {
+        for key in &keys {
+            cache
+                .insert(key.clone(), response.clone(), Duration::from_secs(60))
+                .await;
+        }
+    });
+
+    let mut group = c.benchmark_group("store");
+    group.bench_function("get_hit", |b| {
+        let mut i = 0;
+        b.to_async(&runtime).iter(|| {
+            i = (i + 1) % keys.len();
+            let key = &keys[i];
+            let cache = &cache;
+            async move { black_box(cache.get_serialized(key).await) }
+        })
+    });
+    group.bench_function("get_miss", |b| {
+        b.to_async(&runtime).iter(|| async {
+            black_box(cache.get_serialized("eth_getBalance:[\"missing\"]").await)
+        })
+    });
+    group.bench_function("insert", |b| {
+        let mut i = 0;
+        b.to_async(&runtime).iter(|| {
+            i = (i + 1) % keys.len();
+            let key = keys[i].clone();
+            let cache = &cache;
+            let response = response.clone();
+            async move { cache.insert(key, response, Duration::from_secs(60)).await }
+        })
+    });
+    group.finish();
+}
+
+fn cache_hit_response(c: &mut Criterion) {
+    let logs: Vec<_> = (0..1000)
+        .map(|i| {
+            serde_json::json!({
+                "address": format!("0x{i:040x}"),
+                "topics": [format!("0x{i:064x}"), format!("0x{:064x}", i + 1)],
+                "data": format!("0x{:0128x}", i),
+                "blockNumber": format!("0x{:x}", 0x1000000 + i),
+                "logIndex": format!("0x{i:x}"),
+            })
+        })
+        .collect();
+    let cached = Arc::new(JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        result: Some(serde_json::Value::Array(logs)),
+        error: None,
+        id: serde_json::json!(1),
+    });
+    let id = serde_json::json!(42);
+
+    let mut group = c.benchmark_group("cache_hit_response");
+    group.bench_function("clone_and_set_id", |b| {
+        b.iter(|| {
+            let mut resp = (*cached).clone();
+            resp.id = id.clone();
+            black_box(serde_json::to_vec(&resp).unwrap())
+        })
+    });
+    group.bench_function("with_id", |b| {
+        b.iter(|| black_box(serde_json::to_vec(&cached.with_id(&id)).unwrap()))
+    });
+    let serialized = SerializedResponse::new(&cached);
+    group.bench_function("pre_serialized", |b| {
+        b.iter(|| {
+            let mut out = Vec::new();
+            serialized.write_with_id(&id, &mut out);
+            black_box(out)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, cache_key, store, cache_hit_response);
+criterion_main!(benches);
@@ -1,4 +1,4 @@
 pub mod policy;
 mod store;
 
-pub use store::RpcCache;
+pub use store::{CacheHit, CacheKeyInfo, RpcCache, hash_key};
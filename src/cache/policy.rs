@@ -3,6 +3,10 @@ use std::time::Duration;
 use crate::jsonrpc::JsonRpcRequest;
 
 pub const IMMUTABLE_TTL_SECS: u64 = 3600;
+/// Upper bound on the TTL of `eth_feeHistory` ending at a block tag such as
+/// `latest`: gas estimates drift with every block, so they shouldn't outlive
+/// the chain tip the way a plain `--cache-ttl` might.
+pub const FEE_HISTORY_TIP_TTL_MS: u64 = 1000;
 const NEVER_CACHE_METHODS: &[&str] = &[
     "eth_sendRawTransaction",
     "eth_sendTransaction",
@@ -14,6 +18,16 @@ const NEVER_CACHE_METHODS: &[&str] = &[
     "miner_start",
     "miner_stop",
     "debug_traceTransaction",
+    // Filters are stateful and live on the node that created them: every
+    // `eth_getFilterChanges` call returns only what changed since the previous
+    // call, so a cached or coalesced answer would hand one client's diff to
+    // another and leave both with stale or missing entries.
+    "eth_newFilter",
+    "eth_newBlockFilter",
+    "eth_newPendingTransactionFilter",
+    "eth_getFilterChanges",
+    "eth_getFilterLogs",
+    "eth_uninstallFilter",
 ];
 
 const IMMUTABLE_METHODS: &[&str] = &[
@@ -30,10 +44,152 @@ const IMMUTABLE_METHODS: &[&str] = &[
     "web3_clientVersion",
 ];
 
+/// Methods that read historical state only an archive node keeps.
+const ARCHIVE_METHODS: &[&str] = &[
+    "eth_getProof",
+    "debug_storageRangeAt",
+    "debug_traceBlockByNumber",
+    "debug_traceBlockByHash",
+    "trace_block",
+    "trace_replayBlockTransactions",
+    "trace_filter",
+];
+
+/// Methods whose block parameter may be pinned from `latest` to a number by
+/// `--resolve-latest`, with that parameter's position. `eth_getStorageAt`
+/// and `eth_getProof` are left out: at a block number they count as
+/// archive queries and would be routed differently.
+const LATEST_BLOCK_PARAMS: &[(&str, usize)] = &[
+    ("eth_getBlockByNumber", 0),
+    ("eth_getBlockTransactionCountByNumber", 0),
+    ("eth_getUncleCountByBlockNumber", 0),
+    ("eth_getBalance", 1),
+    ("eth_getTransactionCount", 1),
+    ("eth_getCode", 1),
+    ("eth_call", 1),
+];
+
 pub fn should_cache(method: &str) -> bool {
     !NEVER_CACHE_METHODS.contains(&method)
 }
 
+/// Whether a method needs an archive node, so backends tagged `archive` are
+/// tried first for it.
+pub fn requires_archive(method: &str) -> bool {
+    ARCHIVE_METHODS.contains(&method)
+}
+
+/// Like `requires_archive`, but also true for `eth_getStorageAt` at an
+/// explicit block rather than a tag such as `latest`, which a full node may
+/// have pruned.
+pub fn request_requires_archive(request: &JsonRpcRequest) -> bool {
+    if requires_archive(&request.method) {
+        return true;
+    }
+    request.method == "eth_getStorageAt"
+        && match request.params.get(2) {
+            Some(serde_json::Value::String(block)) => block.starts_with("0x"),
+            // EIP-1898 `{ "blockNumber": ... }` or `{ "blockHash": ... }`.
+            Some(serde_json::Value::Object(_)) => true,
+            _ => false,
+        }
+}
+
+/// Rewrites a `"latest"` block parameter to `block` as a hex quantity, for
+/// the methods where that yields the same answer. Returns whether the
+/// request was changed; an omitted block parameter is left alone.
+pub fn resolve_latest(request: &mut JsonRpcRequest, block: u64) -> bool {
+    let Some(&(_, index)) = LATEST_BLOCK_PARAMS
+        .iter()
+        .find(|(method, _)| *method == request.method)
+    else {
+        return false;
+    };
+    match request.params.get_mut(index) {
+        Some(param) if param.as_str() == Some("latest") => {
+            *param = serde_json::Value::String(format!("{block:#x}"));
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Position of the block parameter of methods that take one.
+fn block_param_index(method: &str) -> Option<usize> {
+    match method {
+        "eth_getTransactionByBlockNumberAndIndex" | "eth_getUncleByBlockNumberAndIndex" => Some(0),
+        "eth_feeHistory" => Some(1),
+        "eth_getStorageAt" | "eth_getProof" => Some(2),
+        _ => LATEST_BLOCK_PARAMS
+            .iter()
+            .find(|(m, _)| *m == method)
+            .map(|&(_, index)| index),
+    }
+}
+
+/// Whether a call asks for a block number past `latest_block`, the best
+/// known head. Such a block doesn't exist yet, so the `null` a node answers
+/// with must not be kept like a pinned block's answer. With no known head
+/// nothing counts as past it.
+pub fn is_beyond_head(request: &JsonRpcRequest, latest_block: Option<u64>) -> bool {
+    let Some(latest) = latest_block else {
+        return false;
+    };
+    let Some(param) = block_param_index(&request.method).and_then(|i| request.params.get(i)) else {
+        return false;
+    };
+    // EIP-1898 `{ "blockNumber": ... }`.
+    let number = param.get("blockNumber").unwrap_or(param);
+    number
+        .as_str()
+        .and_then(|s| s.strip_prefix("0x"))
+        .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+        .is_some_and(|block| block > latest)
+}
+
+/// Whether a method submits transactions or changes node state, as refused
+/// by `--read-only`. Unlike `should_cache`, read-only methods that merely
+/// shouldn't be cached (filters, traces) are not writes.
+pub fn is_write_method(method: &str) -> bool {
+    matches!(method, "eth_sendRawTransaction" | "eth_sendTransaction")
+        || ["personal_", "miner_", "admin_"]
+            .iter()
+            .any(|prefix| method.starts_with(prefix))
+}
+
+/// Like `ttl_for_request`, but gas price lookups (`eth_gasPrice`,
+/// `eth_maxPriorityFeePerGas`, and `eth_feeHistory` at the tip) use `gas_ttl`
+/// when one is given.
+pub fn ttl_with_gas_override(
+    request: &JsonRpcRequest,
+    default_ttl: Duration,
+    gas_ttl: Option<Duration>,
+) -> Duration {
+    if let Some(gas_ttl) = gas_ttl
+        && is_gas_price_request(request)
+    {
+        return gas_ttl;
+    }
+    ttl_for_request(request, default_ttl)
+}
+
+fn is_gas_price_request(request: &JsonRpcRequest) -> bool {
+    match request.method.as_str() {
+        "eth_gasPrice" | "eth_maxPriorityFeePerGas" => true,
+        "eth_feeHistory" => !fee_history_is_pinned(request),
+        _ => false,
+    }
+}
+
+/// Whether an `eth_feeHistory` call ends at a specific block rather than a tag.
+fn fee_history_is_pinned(request: &JsonRpcRequest) -> bool {
+    match request.params.as_array().and_then(|a| a.get(1)) {
+        Some(serde_json::Value::String(s)) => s.starts_with("0x"),
+        Some(serde_json::Value::Number(_)) => true,
+        _ => false,
+    }
+}
+
 pub fn ttl_for_request(request: &JsonRpcRequest, default_ttl: Duration) -> Duration {
     let method = request.method.as_str();
 
@@ -50,6 +206,16 @@ pub fn ttl_for_request(request: &JsonRpcRequest, default_ttl: Duration) -> Durat
         return Duration::from_secs(IMMUTABLE_TTL_SECS);
     }
 
+    // eth_feeHistory ending at a specific block is immutable; ending at a tag
+    // it moves with the tip. Block count and reward percentiles are part of
+    // the params, so they're already in the cache key.
+    if method == "eth_feeHistory" {
+        if fee_history_is_pinned(request) {
+            return Duration::from_secs(IMMUTABLE_TTL_SECS);
+        }
+        return default_ttl.min(Duration::from_millis(FEE_HISTORY_TIP_TTL_MS));
+    }
+
     // eth_getLogs with a specific blockHash is immutable
     if method == "eth_getLogs"
         && let Some(filter) = request.params.as_array().and_then(|a| a.first())
@@ -1,18 +1,54 @@
+use std::borrow::Cow;
+use std::fmt::Write;
 use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
+use dashmap::DashMap;
 use moka::Expiry;
 use moka::future::Cache;
-use tokio::sync::RwLock;
+use ring::digest;
 use tokio::sync::broadcast;
 use tracing::trace;
 
-use crate::jsonrpc::JsonRpcResponse;
+use crate::jsonrpc::{JsonRpcRequest, JsonRpcResponse, SerializedResponse};
+
+use super::policy;
+
+/// JSON-RPC error code for a method the node doesn't implement.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Cache keys whose last upstream error is remembered for the cooldown.
+const ERROR_COOLDOWN_CAPACITY: u64 = 10_000;
 
 #[derive(Clone)]
 struct CacheEntry {
-    response: Arc<JsonRpcResponse>,
+    /// The response pre-serialized without its id, so hits skip
+    /// serialization. Only the bytes are kept, not the parsed response.
+    serialized: SerializedResponse,
+    /// Hash of `serialized`, which leaves out the id, so it changes only
+    /// when the cached content does.
+    content_hash: u64,
     ttl: Duration,
+    inserted_at: Instant,
+}
+
+/// One entry as listed by `RpcCache::keys`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CacheKeyInfo {
+    /// The key as stored: namespaced, and hashed with hashed keys.
+    pub key: String,
+    pub ttl_remaining_ms: u64,
+    /// Serialized response size, excluding the id.
+    pub size_bytes: usize,
+}
+
+/// A cache hit: the pre-serialized response, the entry's content hash, and
+/// how long it has left before it expires.
+pub struct CacheHit {
+    pub body: SerializedResponse,
+    pub content_hash: u64,
+    pub remaining: Duration,
 }
 
 struct PerEntryExpiry;
@@ -22,7 +58,7 @@ impl Expiry<String, CacheEntry> for PerEntryExpiry {
         &self,
         _key: &String,
         value: &CacheEntry,
-        _current_time: std::time::Instant,
+        _current_time: Instant,
     ) -> Option<Duration> {
         Some(value.ttl)
     }
@@ -32,8 +68,45 @@ impl Expiry<String, CacheEntry> for PerEntryExpiry {
 pub struct RpcCache {
     cache: Cache<String, CacheEntry>,
     default_ttl: Duration,
-    inflight:
-        Arc<RwLock<std::collections::HashMap<String, broadcast::Sender<Arc<JsonRpcResponse>>>>>,
+    /// TTL for gas price lookups, overriding `default_ttl` for them.
+    gas_ttl: Option<Duration>,
+    /// TTL for `-32601 Method not found` errors. Other errors aren't cached.
+    method_not_found_ttl: Option<Duration>,
+    /// The last upstream error per cache key, kept for the error cooldown so
+    /// identical calls get it back instead of going upstream again.
+    recent_errors: Option<Cache<String, Arc<JsonRpcResponse>>>,
+    /// Whether answers about blocks past the head are cached, for at most
+    /// the default TTL.
+    cache_future_blocks: bool,
+    /// Cleared by `--no-cache`: nothing is looked up, stored, or coalesced.
+    enabled: bool,
+    /// In-flight upstream calls by cache key. Sharded so that requests for
+    /// distinct keys don't serialize on a single lock.
+    inflight: Arc<DashMap<String, broadcast::Sender<Arc<JsonRpcResponse>>>>,
+    /// Store entries under `hash_key(key)` instead of the readable key.
+    hash_keys: bool,
+    /// Prepended to every key so caches for different chains sharing one
+    /// store can't serve each other's entries.
+    namespace: Option<String>,
+    /// Lookups through `get_serialized` since startup, shared by clones.
+    stats: Arc<CacheStats>,
+}
+
+#[derive(Default)]
+struct CacheStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// The fixed-size key an entry is stored under with hashed keys: the hex
+/// SHA-256 of the readable cache key.
+pub fn hash_key(key: &str) -> String {
+    let digest = digest::digest(&digest::SHA256, key.as_bytes());
+    let mut hex = String::with_capacity(digest.as_ref().len() * 2);
+    for byte in digest.as_ref() {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
 }
 
 impl RpcCache {
@@ -46,48 +119,245 @@ impl RpcCache {
         Self {
             cache,
             default_ttl: Duration::from_millis(default_ttl_ms),
-            inflight: Arc::new(RwLock::new(std::collections::HashMap::new())),
+            gas_ttl: None,
+            method_not_found_ttl: None,
+            recent_errors: None,
+            cache_future_blocks: true,
+            enabled: true,
+            inflight: Arc::new(DashMap::new()),
+            hash_keys: false,
+            namespace: None,
+            stats: Arc::new(CacheStats::default()),
         }
     }
 
-    pub async fn get(&self, key: &str) -> Option<Arc<JsonRpcResponse>> {
-        let result = self.cache.get(key).await;
-        if let Some(entry) = &result {
-            trace!(key = %key, "cache hit");
-            return Some(entry.response.clone());
+    /// Stores entries under a SHA-256 of the cache key rather than the key
+    /// itself. Large `eth_getLogs` filters make for multi-kilobyte keys, so
+    /// this bounds per-entry key memory at 64 bytes. Callers keep passing the
+    /// readable key, which is what gets logged.
+    pub fn with_hashed_keys(mut self, enabled: bool) -> Self {
+        self.hash_keys = enabled;
+        self
+    }
+
+    /// Turns the cache off when `enabled` is false, so every request goes
+    /// straight upstream.
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Caches gas price lookups for `gas_ttl` instead of the default TTL.
+    pub fn with_gas_ttl(mut self, gas_ttl: Option<Duration>) -> Self {
+        self.gas_ttl = gas_ttl;
+        self
+    }
+
+    /// Caches `-32601 Method not found` errors for `ttl`. Without it, error
+    /// responses are never stored.
+    pub fn with_method_not_found_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.method_not_found_ttl = ttl;
+        self
+    }
+
+    /// Remembers an upstream error that isn't cached for `cooldown`, so
+    /// identical calls are answered with it instead of all going upstream
+    /// while the error persists.
+    pub fn with_error_cooldown(mut self, cooldown: Option<Duration>) -> Self {
+        self.recent_errors = cooldown.filter(|c| !c.is_zero()).map(|cooldown| {
+            Cache::builder()
+                .max_capacity(ERROR_COOLDOWN_CAPACITY)
+                .time_to_live(cooldown)
+                .build()
+        });
+        self
+    }
+
+    /// Stops caching answers about blocks past the head, which are otherwise
+    /// kept for at most the default TTL.
+    pub fn with_future_blocks_cached(mut self, cached: bool) -> Self {
+        self.cache_future_blocks = cached;
+        self
+    }
+
+    /// Prefixes every key with `namespace:`. Clones sharing this cache's
+    /// storage but using different namespaces never see each other's entries.
+    pub fn with_namespace(mut self, namespace: Option<String>) -> Self {
+        self.namespace = namespace.filter(|ns| !ns.is_empty());
+        self
+    }
+
+    fn storage_key<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        let key = match &self.namespace {
+            Some(namespace) => Cow::Owned(format!("{namespace}:{key}")),
+            None => Cow::Borrowed(key),
+        };
+        if self.hash_keys {
+            Cow::Owned(hash_key(&key))
+        } else {
+            key
         }
-        None
+    }
+
+    /// Whether a live entry exists for `key`, without counting a lookup.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.cache.contains_key(self.storage_key(key).as_ref())
+    }
+
+    pub async fn get_serialized(&self, key: &str) -> Option<CacheHit> {
+        let Some(entry) = self.cache.get(self.storage_key(key).as_ref()).await else {
+            self.stats.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        self.stats.hits.fetch_add(1, Ordering::Relaxed);
+        trace!(key = %key, "cache hit");
+        Some(CacheHit {
+            remaining: entry.ttl.saturating_sub(entry.inserted_at.elapsed()),
+            content_hash: entry.content_hash,
+            body: entry.serialized,
+        })
     }
 
     pub async fn insert(&self, key: String, response: Arc<JsonRpcResponse>, ttl: Duration) {
-        self.cache.insert(key, CacheEntry { response, ttl }).await;
+        let serialized = SerializedResponse::new(&response);
+        let content_hash = serialized.content_hash();
+        let entry = CacheEntry {
+            serialized,
+            content_hash,
+            ttl,
+            inserted_at: Instant::now(),
+        };
+        let key = match self.storage_key(&key) {
+            Cow::Owned(hashed) => hashed,
+            Cow::Borrowed(_) => key,
+        };
+        self.cache.insert(key, entry).await;
+    }
+
+    /// The error an identical call got within the error cooldown, if any.
+    pub async fn recent_error(&self, key: &str) -> Option<Arc<JsonRpcResponse>> {
+        let errors = self.recent_errors.as_ref()?;
+        errors.get(self.storage_key(key).as_ref()).await
+    }
+
+    /// Records an upstream error response for the error cooldown. Does
+    /// nothing without one.
+    pub async fn note_error(&self, key: &str, response: Arc<JsonRpcResponse>) {
+        if let Some(errors) = &self.recent_errors {
+            errors
+                .insert(self.storage_key(key).into_owned(), response)
+                .await;
+        }
     }
 
     pub async fn subscribe_inflight(
         &self,
         key: &str,
     ) -> Option<broadcast::Receiver<Arc<JsonRpcResponse>>> {
-        let inflight = self.inflight.read().await;
-        inflight.get(key).map(|tx| tx.subscribe())
+        self.inflight
+            .get(self.storage_key(key).as_ref())
+            .map(|tx| tx.subscribe())
     }
 
-    pub async fn register_inflight(&self, key: &str) -> broadcast::Sender<Arc<JsonRpcResponse>> {
-        let (tx, _) = broadcast::channel(1);
-        let mut inflight = self.inflight.write().await;
-        inflight.insert(key.to_string(), tx.clone());
-        tx
+    /// Registers a call to `key` as in flight unless one already is. Returns
+    /// the call's sender and whether this caller registered it, and so has to
+    /// answer it; otherwise it can subscribe to wait for the answer.
+    pub async fn register_inflight(
+        &self,
+        key: &str,
+    ) -> (broadcast::Sender<Arc<JsonRpcResponse>>, bool) {
+        let mut leader = false;
+        let tx = self
+            .inflight
+            .entry(self.storage_key(key).into_owned())
+            .or_insert_with(|| {
+                leader = true;
+                broadcast::channel(1).0
+            })
+            .clone();
+        (tx, leader)
     }
 
     pub async fn remove_inflight(&self, key: &str) {
-        let mut inflight = self.inflight.write().await;
-        inflight.remove(key);
+        self.inflight.remove(self.storage_key(key).as_ref());
     }
 
     pub fn default_ttl(&self) -> Duration {
         self.default_ttl
     }
 
+    /// Whether caching is on: not disabled with `with_enabled(false)`, and
+    /// the default TTL isn't zero. When off, immutable methods aren't cached
+    /// either and requests aren't coalesced.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled && !self.default_ttl.is_zero()
+    }
+
+    /// Whether responses to `method` go through the cache: caching is
+    /// enabled and the cache policy allows the method.
+    pub fn should_cache(&self, method: &str) -> bool {
+        self.is_enabled() && policy::should_cache(method)
+    }
+
+    /// How long a response to `request` should be cached.
+    pub fn ttl_for(&self, request: &JsonRpcRequest) -> Duration {
+        policy::ttl_with_gas_override(request, self.default_ttl, self.gas_ttl)
+    }
+
+    /// How long `response` to `request` may be cached, or `None` if it must
+    /// not be: successes get `ttl_for`, `-32601` errors the method-not-found
+    /// TTL if one is set, and any other error nothing. A call for a block past
+    /// `latest_block` gets at most the default TTL, or nothing if future
+    /// blocks aren't cached, since its answer changes once the block exists.
+    pub fn storable_ttl(
+        &self,
+        request: &JsonRpcRequest,
+        response: &JsonRpcResponse,
+        latest_block: Option<u64>,
+    ) -> Option<Duration> {
+        match &response.error {
+            None if policy::is_beyond_head(request, latest_block) => self
+                .cache_future_blocks
+                .then(|| self.ttl_for(request).min(self.default_ttl)),
+            None => Some(self.ttl_for(request)),
+            Some(error) if error.code == METHOD_NOT_FOUND => self.method_not_found_ttl,
+            Some(_) => None,
+        }
+    }
+
     pub async fn entry_count(&self) -> u64 {
         self.cache.entry_count()
     }
+
+    /// Hits and misses of request lookups since startup.
+    pub fn hit_counts(&self) -> (u64, u64) {
+        (
+            self.stats.hits.load(Ordering::Relaxed),
+            self.stats.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Up to `limit` live entries in key order, starting at `offset`, and
+    /// the total number of live entries. Walks the whole cache, so it's
+    /// meant for diagnostics only.
+    pub fn keys(&self, offset: usize, limit: usize) -> (usize, Vec<CacheKeyInfo>) {
+        let mut entries: Vec<_> = self
+            .cache
+            .iter()
+            .map(|(key, entry)| CacheKeyInfo {
+                key: key.as_ref().clone(),
+                ttl_remaining_ms: entry
+                    .ttl
+                    .saturating_sub(entry.inserted_at.elapsed())
+                    .as_millis() as u64,
+                size_bytes: entry.serialized.size(),
+            })
+            .collect();
+        entries.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+        let total = entries.len();
+        (
+            total,
+            entries.into_iter().skip(offset).take(limit).collect(),
+        )
+    }
 }
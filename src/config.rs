@@ -1,4 +1,14 @@
-use clap::Parser;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use axum::http::HeaderName;
+use clap::builder::ArgPredicate;
+use clap::{Parser, ValueEnum};
+
+use crate::handler::transform::StripRule;
+use crate::upstream::{Target, TargetHeader, sanitize_url};
 
 #[derive(Parser, Debug, Clone)]
 #[command(name = "rpcproxy", about = "High-performance JSON-RPC reverse proxy")]
@@ -7,31 +17,225 @@ pub struct Config {
     #[arg(long, env = "RPCPROXY_PORT", default_value = "9000")]
     pub port: u16,
 
-    /// Comma-separated list of upstream RPC URLs (priority order)
+    /// IP address to listen on (e.g. `127.0.0.1` for a local sidecar, `::` for IPv6)
+    #[arg(long, env = "RPCPROXY_BIND", default_value = "0.0.0.0")]
+    pub bind: IpAddr,
+
+    /// Listen on this Unix domain socket instead of TCP. `--bind` and `--port`
+    /// are ignored for serving when set.
+    #[arg(long, env = "RPCPROXY_UNIX_SOCKET")]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Accept HTTP/2 (prior knowledge / h2c) from clients in addition to HTTP/1.1
+    #[arg(long, env = "RPCPROXY_HTTP2", default_value = "false")]
+    pub http2: bool,
+
+    /// Inbound keep-alive timeout in seconds. Idle HTTP/1.1 connections are
+    /// closed after this long; HTTP/2 connections are pinged at this interval.
+    #[arg(long, env = "RPCPROXY_KEEPALIVE_TIMEOUT")]
+    pub keepalive_timeout: Option<u64>,
+
+    /// Comma-separated list of upstream RPC URLs (priority order).
+    /// A static header can be attached to a target with `;header=Name:value`,
+    /// e.g. `https://provider;header=X-Api-Key:abc123`. Tags for `--route-tag`
+    /// are attached with `;tags=archive+debug`.
     #[arg(
         long,
         env = "RPCPROXY_TARGETS",
         default_value = "http://localhost:8545",
+        default_value_if("targets_file", ArgPredicate::IsPresent, None),
         value_delimiter = ','
     )]
     pub targets: Vec<String>,
 
-    /// Default cache TTL in milliseconds
-    #[arg(long, env = "RPCPROXY_CACHE_TTL", default_value = "2000")]
+    /// File listing upstream targets one per line, in `--targets` syntax,
+    /// tried after those given with `--targets`. Blank lines and lines
+    /// starting with `#` are ignored.
+    #[arg(long, env = "RPCPROXY_TARGETS_FILE")]
+    pub targets_file: Option<PathBuf>,
+
+    /// Shadow target receiving a copy of read-only traffic. Its responses are
+    /// compared with the primary's and divergences are logged and counted in
+    /// `/status`; clients always get the primary's response. Accepts the same
+    /// `;header=` options as `--targets`.
+    #[arg(long, env = "RPCPROXY_MIRROR_TARGET")]
+    pub mirror_target: Option<String>,
+
+    /// Fraction of eligible requests (0.0–1.0) copied to `--mirror-target`
+    #[arg(long, env = "RPCPROXY_MIRROR_RATE", default_value = "1.0", value_parser = parse_fraction)]
+    pub mirror_rate: f64,
+
+    /// Default cache TTL in milliseconds. 0 disables caching entirely;
+    /// otherwise at least 100 ms.
+    #[arg(long, env = "RPCPROXY_CACHE_TTL", default_value = "2000", value_parser = parse_cache_ttl)]
     pub cache_ttl: u64,
 
     /// Health check interval in seconds
     #[arg(long, env = "RPCPROXY_HEALTH_INTERVAL", default_value = "1800")]
     pub health_interval: u64,
 
+    /// Log cache and backend stats at info level every this many seconds
+    /// (0 = never)
+    #[arg(long, env = "RPCPROXY_STATS_INTERVAL", default_value = "0")]
+    pub stats_interval: u64,
+
+    /// Number of healthy backends (with a known block) required before `/health`
+    /// and `/readiness` report ready
+    #[arg(long, env = "RPCPROXY_MIN_HEALTHY_BACKENDS", default_value = "1")]
+    pub min_healthy_backends: usize,
+
+    /// Fail readiness if no backend's latest block has advanced within this many
+    /// seconds. Catches nodes that answer but have stopped syncing. Should be
+    /// larger than `--health-interval`, since blocks are observed by the health checker.
+    #[arg(long, env = "RPCPROXY_MAX_BLOCK_AGE_SECS")]
+    pub max_block_age_secs: Option<u64>,
+
+    /// Only count a backend toward readiness if a health probe reported its
+    /// block within this many seconds, advanced or not. Bounds how long a
+    /// block number is trusted once probes stop succeeding. Should be larger
+    /// than `--health-interval`.
+    #[arg(long, env = "RPCPROXY_BLOCK_FRESHNESS_SECS")]
+    pub block_freshness_secs: Option<u64>,
+
+    /// Also call `eth_syncing` in each health check and keep a backend that
+    /// reports itself syncing `Degraded`, out of readiness, until it is done.
+    #[arg(long, env = "RPCPROXY_CHECK_SYNCING")]
+    pub check_syncing: bool,
+
+    /// Split `eth_getLogs` calls spanning more than this many blocks into
+    /// sub-range queries and merge the logs into one response. Avoids provider
+    /// limits such as "query returned more than 10000 results".
+    #[arg(long, env = "RPCPROXY_SPLIT_GETLOGS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub split_getlogs: Option<u64>,
+
+    /// Rewrite `"latest"` in the block parameter of calls such as
+    /// `eth_getBalance` and `eth_getBlockByNumber` to the best block a health
+    /// probe reported within this many seconds, so they are cached against
+    /// that block. Keep `--health-interval` below this, or nothing resolves.
+    #[arg(long, env = "RPCPROXY_RESOLVE_LATEST", value_parser = clap::value_parser!(u64).range(1..))]
+    pub resolve_latest: Option<u64>,
+
+    /// Stream non-cacheable upstream responses larger than this many bytes
+    /// directly to the client instead of buffering and parsing them. Cuts peak
+    /// memory on very large responses such as `debug_traceTransaction`.
+    #[arg(long, env = "RPCPROXY_STREAM_THRESHOLD_BYTES")]
+    pub stream_threshold_bytes: Option<usize>,
+
+    /// JSON-RPC error code returned when every backend failed, so clients can
+    /// tell a retryable outage apart from `-32603 Internal error`.
+    #[arg(
+        long,
+        env = "RPCPROXY_UPSTREAM_DOWN_CODE",
+        default_value = "-32004",
+        allow_hyphen_values = true
+    )]
+    pub upstream_down_code: i64,
+
+    /// HTTP status (e.g. 503) for a single request that failed on every backend.
+    /// By default the error is returned with HTTP 200 like any JSON-RPC error.
+    /// Batches always use 200.
+    #[arg(long, env = "RPCPROXY_UPSTREAM_DOWN_STATUS", value_parser = clap::value_parser!(u16).range(200..=599))]
+    pub upstream_down_status: Option<u16>,
+
+    /// HTTP status (e.g. 400) for a single request answered with a JSON-RPC
+    /// error returned by a backend. By default JSON-RPC errors use HTTP 200.
+    /// Protocol errors the proxy produces itself, such as parse errors and
+    /// invalid requests, and batches always use 200.
+    #[arg(long, env = "RPCPROXY_ERROR_HTTP_STATUS", value_parser = clap::value_parser!(u16).range(200..=599))]
+    pub error_http_status: Option<u16>,
+
+    /// Seconds to skip a backend after it answers HTTP 429, so a rate-limited
+    /// provider is given room to recover. A longer `Retry-After` from the
+    /// backend takes precedence. 0 relies on `Retry-After` alone.
+    #[arg(long, env = "RPCPROXY_RATE_LIMIT_COOLDOWN_SECS", default_value = "2")]
+    pub rate_limit_cooldown_secs: u64,
+
+    /// Route requests that carry the same value in this header (e.g.
+    /// `X-Session-Id`) to the same backend. Needed for node-local state such as
+    /// filters created with `eth_newFilter`.
+    #[arg(long, env = "RPCPROXY_STICKY_HEADER", value_parser = HeaderName::from_str)]
+    pub sticky_header: Option<HeaderName>,
+
     /// Upstream request timeout in seconds
     #[arg(long, env = "RPCPROXY_REQUEST_TIMEOUT", default_value = "10")]
     pub request_timeout: u64,
 
+    /// Timeout in seconds for establishing an upstream connection, so an
+    /// unreachable host fails over quickly while `--request-timeout` still
+    /// allows slow responses. Bounded only by the request timeout if unset.
+    #[arg(long, env = "RPCPROXY_CONNECT_TIMEOUT")]
+    pub connect_timeout: Option<u64>,
+
+    /// Maximum idle keep-alive connections kept open per upstream host
+    #[arg(long, env = "RPCPROXY_POOL_MAX_IDLE_PER_HOST", default_value = "20")]
+    pub pool_max_idle_per_host: usize,
+
+    /// Seconds an idle upstream connection is kept before being closed
+    #[arg(long, env = "RPCPROXY_POOL_IDLE_TIMEOUT", default_value = "90")]
+    pub pool_idle_timeout: u64,
+
+    /// Force HTTP/2 (prior knowledge) for upstream connections.
+    /// Only enable this if every target supports HTTP/2.
+    #[arg(long, env = "RPCPROXY_UPSTREAM_HTTP2", default_value = "false")]
+    pub upstream_http2: bool,
+
+    /// Send a client batch upstream as one batch when none of its calls is
+    /// cached and all of them share a route, instead of one request per call.
+    /// Falls back to per-call requests if the backend's answer doesn't match.
+    #[arg(long, env = "RPCPROXY_UPSTREAM_BATCHING")]
+    pub upstream_batching: bool,
+
+    /// Send every upstream call of a batch to the backend that answered its
+    /// first one, so `latest` means the same block throughout the batch.
+    /// Failover still applies if that backend fails.
+    #[arg(long, env = "RPCPROXY_BATCH_CONSISTENCY")]
+    pub batch_consistency: bool,
+
+    /// Send all upstream traffic, health probes included, through this
+    /// proxy: `http://`, `https://`, `socks5://`, or `socks5h://` (DNS
+    /// resolved by the proxy). Credentials may be given as `user:pass@`.
+    #[arg(long, env = "RPCPROXY_UPSTREAM_PROXY", value_parser = parse_upstream_proxy)]
+    pub upstream_proxy: Option<reqwest::Proxy>,
+
     /// Maximum number of cached entries
     #[arg(long, env = "RPCPROXY_CACHE_MAX_SIZE", default_value = "10000")]
     pub cache_max_size: u64,
 
+    /// Store cache entries under a SHA-256 of the cache key instead of the
+    /// full method and params, bounding key memory for large `eth_getLogs` filters.
+    #[arg(long, env = "RPCPROXY_HASH_CACHE_KEYS", default_value = "false")]
+    pub hash_cache_keys: bool,
+
+    /// Cache TTL in milliseconds for gas price lookups (`eth_gasPrice`,
+    /// `eth_maxPriorityFeePerGas`, `eth_feeHistory` at the tip). Defaults to
+    /// the regular policy.
+    #[arg(long, env = "RPCPROXY_GAS_TTL_MS")]
+    pub gas_ttl_ms: Option<u64>,
+
+    /// Cache a `-32601 Method not found` error from upstream for this many
+    /// milliseconds, so an unsupported method isn't asked for again on every
+    /// call. Errors are not cached by default.
+    #[arg(long, env = "RPCPROXY_METHOD_NOT_FOUND_TTL_MS")]
+    pub method_not_found_ttl_ms: Option<u64>,
+
+    /// After an upstream returns a JSON-RPC error for a cacheable call, answer
+    /// identical calls with that error for this many milliseconds instead of
+    /// forwarding them. Lighter than caching errors: nothing is stored in the
+    /// response cache.
+    #[arg(long, env = "RPCPROXY_ERROR_COOLDOWN_MS")]
+    pub error_cooldown_ms: Option<u64>,
+
+    /// Don't cache answers about a block number past the best block the
+    /// health checks have seen. By default they are cached for at most
+    /// `--cache-ttl`, as the block will soon exist.
+    #[arg(long, env = "RPCPROXY_NO_CACHE_FUTURE_BLOCKS")]
+    pub no_cache_future_blocks: bool,
+
+    /// Prefix for every cache key, e.g. a chain name, so proxies for
+    /// different chains never share cache entries.
+    #[arg(long, env = "RPCPROXY_CACHE_NAMESPACE")]
+    pub cache_namespace: Option<String>,
+
     /// Token for authenticating requests. If set, RPC requests require either
     /// the token in the URL path (`POST /<token>`) or a Bearer header
     /// (`Authorization: Bearer <token>`). The `/readiness` and `/status` endpoints
@@ -46,11 +250,255 @@ pub struct Config {
     #[arg(short, long, env = "RPCPROXY_VERBOSE", default_value = "false")]
     pub verbose: bool,
 
+    /// Emit `Cache-Control` headers on RPC responses: `public, max-age=<remaining TTL>`
+    /// for cached responses and `no-store` for everything else. Only useful when
+    /// the proxy sits behind a caching layer such as a CDN.
+    #[arg(long, env = "RPCPROXY_CACHE_CONTROL_HEADERS", default_value = "false")]
+    pub cache_control_headers: bool,
+
+    /// Log and report backend URLs in full. By default API keys in the path,
+    /// query strings, and credentials are masked. Intended for local debugging.
+    #[arg(long, env = "RPCPROXY_SHOW_FULL_URLS", default_value = "false")]
+    pub show_full_urls: bool,
+
+    /// Log output format: human-readable `text` or structured `json`
+    /// (one object per line, for log aggregators).
+    #[arg(long, env = "RPCPROXY_LOG_FORMAT", value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+
+    /// OTLP/HTTP traces endpoint (e.g. `http://collector:4318/v1/traces`).
+    /// When set, request and upstream spans are exported there, continuing
+    /// any trace the client started with a `traceparent` header.
+    #[cfg(feature = "otel")]
+    #[arg(long, env = "RPCPROXY_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Remove a field from a method's result before it is cached and returned,
+    /// as `method:path.to.field` (repeatable, or comma-separated). Arrays on the
+    /// path are descended element-wise, e.g. `eth_getBlockByNumber:transactions`.
+    #[arg(
+        long = "strip-field",
+        env = "RPCPROXY_STRIP_FIELDS",
+        value_delimiter = ','
+    )]
+    pub strip_fields: Vec<String>,
+
+    /// Rewrite a method name before caching and forwarding, as `old=new`
+    /// (repeatable, or comma-separated). Useful for legacy clients calling
+    /// deprecated or vendor-specific method names.
+    #[arg(
+        long = "method-alias",
+        env = "RPCPROXY_METHOD_ALIASES",
+        value_delimiter = ','
+    )]
+    pub method_aliases: Vec<String>,
+
+    /// Give a method its own upstream request timeout, as `method=secs`,
+    /// replacing `--request-timeout` for it (repeatable, or comma-separated).
+    #[arg(
+        long = "method-timeout",
+        env = "RPCPROXY_METHOD_TIMEOUTS",
+        value_delimiter = ','
+    )]
+    pub method_timeouts: Vec<String>,
+
+    /// Answer a method only once two backends return the same result,
+    /// asking further backends while they disagree (repeatable, or
+    /// comma-separated), e.g. `eth_getBalance`.
+    #[arg(
+        long = "quorum-method",
+        env = "RPCPROXY_QUORUM_METHODS",
+        value_delimiter = ','
+    )]
+    pub quorum_methods: Vec<String>,
+
+    /// Pin a method to specific backends, as `method=index` where `index` is
+    /// the target's 0-based position in `--targets`. Repeat the flag to allow
+    /// several backends; methods without a route may use any backend.
+    #[arg(long = "route", env = "RPCPROXY_ROUTES", value_delimiter = ',')]
+    pub routes: Vec<String>,
+
+    /// Pin a method to the backends tagged with `tag`, as `method=tag`.
+    /// Combines with `--route`; repeat the flag to accept several tags.
+    #[arg(long = "route-tag", env = "RPCPROXY_ROUTE_TAGS", value_delimiter = ',')]
+    pub route_tags: Vec<String>,
+
+    /// Return an error as soon as every backend has failed, instead of
+    /// retrying one backend once more as a last resort.
+    #[arg(long, env = "RPCPROXY_NO_LAST_RESORT", default_value = "false")]
+    pub no_last_resort: bool,
+
+    /// Wait a random 0 to this many milliseconds before the last-resort
+    /// retry, so requests that failed together don't all retry at once.
+    #[arg(long, env = "RPCPROXY_RETRY_JITTER_MS", default_value = "0")]
+    pub retry_jitter_ms: u64,
+
+    /// Cap in seconds on the total time spent on one request across all
+    /// backends, failover included. Separate from `--request-timeout`, which
+    /// applies to each upstream attempt.
+    #[arg(long, env = "RPCPROXY_TOTAL_REQUEST_TIMEOUT")]
+    pub total_request_timeout: Option<u64>,
+
+    /// Include upstream detail (backends tried, their errors, elapsed time) in
+    /// `error.data` of failed calls. Off by default so internals don't leak.
+    #[arg(long, env = "RPCPROXY_VERBOSE_ERRORS", default_value = "false")]
+    pub verbose_errors: bool,
+
+    /// Accept requests whose `jsonrpc` field is missing or `"1.0"` from legacy
+    /// clients, forwarding them as `"2.0"`. By default they are rejected with
+    /// `-32600 Invalid request`.
+    #[arg(long, env = "RPCPROXY_LENIENT_JSONRPC", default_value = "false")]
+    pub lenient_jsonrpc: bool,
+
+    /// Forward requests with omitted or `null` params as `params: []`, for
+    /// upstreams that reject `null`.
+    #[arg(
+        long = "params-normalize",
+        env = "RPCPROXY_PARAMS_NORMALIZE",
+        default_value = "false"
+    )]
+    pub normalize_params: bool,
+
+    /// Log a warning for every upstream call slower than this many
+    /// milliseconds, and count them per backend in `/status`.
+    #[arg(long, env = "RPCPROXY_SLOW_QUERY_THRESHOLD_MS")]
+    pub slow_query_threshold_ms: Option<u64>,
+
+    /// POST a JSON event (`backend`, `old_state`, `new_state`, `timestamp`)
+    /// to this URL whenever a backend changes state, e.g. Healthy to Down.
+    #[arg(long, env = "RPCPROXY_STATE_CHANGE_WEBHOOK")]
+    pub state_change_webhook: Option<String>,
+
+    /// Seconds over which a backend's state changes are coalesced into one
+    /// webhook event. A backend that flaps back to its original state within
+    /// the window sends nothing.
+    #[arg(long, env = "RPCPROXY_STATE_CHANGE_DEBOUNCE_SECS", default_value = "5")]
+    pub state_change_debounce_secs: u64,
+
+    /// Seconds after startup during which backends that haven't yet passed a
+    /// health probe are only used once every probed backend has failed.
+    /// 0 routes to them right away.
+    #[arg(long, env = "RPCPROXY_STARTUP_GRACE", default_value = "0")]
+    pub startup_grace: u64,
+
+    /// Seconds a backend must stay `Healthy` after recovering before it gets
+    /// its place in the priority order back. Until then it is tried after
+    /// the backends that stayed healthy. 0 fails back right away.
+    #[arg(long, env = "RPCPROXY_FAILBACK_DELAY", default_value = "0")]
+    pub failback_delay: u64,
+
+    /// Refuse methods that submit transactions or change node state
+    /// (`eth_sendRawTransaction`, `eth_sendTransaction`, `personal_*`,
+    /// `miner_*`, `admin_*`) with JSON-RPC error -32601.
+    #[arg(long, env = "RPCPROXY_READ_ONLY")]
+    pub read_only: bool,
+
+    /// Don't pass the client's `traceparent`/`tracestate` headers on to the
+    /// backends.
+    #[arg(long, env = "RPCPROXY_NO_TRACE_PROPAGATION")]
+    pub no_trace_propagation: bool,
+
+    /// HTTP status for requests rejected for a missing or bad token, on the
+    /// RPC and status endpoints alike (e.g. 403).
+    #[arg(long, env = "RPCPROXY_UNAUTHORIZED_STATUS", default_value = "401", value_parser = clap::value_parser!(u16).range(400..=499))]
+    pub unauthorized_status: u16,
+
+    /// Also accept the token as `?token=<token>` on the RPC endpoints, for
+    /// integrations that can't set headers or paths. Off by default: query
+    /// strings tend to end up in access logs and browser history.
+    #[arg(long, env = "RPCPROXY_ALLOW_QUERY_TOKEN")]
+    pub allow_query_token: bool,
+
+    /// Disable response caching and request coalescing, so every request
+    /// goes straight to an upstream.
+    #[arg(long, env = "RPCPROXY_NO_CACHE")]
+    pub no_cache: bool,
+
+    /// Send ETags with cached responses and answer matching `If-None-Match`
+    /// requests with 304 Not Modified.
+    #[arg(long, env = "RPCPROXY_ETAG")]
+    pub etag: bool,
+
+    /// Abandon an upstream response once its body passes this many bytes and
+    /// return an error asking the client to narrow the request.
+    #[arg(long, env = "RPCPROXY_MAX_RESPONSE_BYTES")]
+    pub max_response_bytes: Option<usize>,
+
+    /// Call a cacheable, parameterless method once a backend is up, so the
+    /// cache already holds it when clients arrive (repeatable, or
+    /// comma-separated), e.g. `eth_chainId`.
+    #[arg(
+        long = "warm-method",
+        env = "RPCPROXY_WARM_METHODS",
+        value_delimiter = ','
+    )]
+    pub warm_methods: Vec<String>,
+
+    /// Allow browser requests from this origin, e.g. `https://app.example`
+    /// (repeatable, or comma-separated), or from any origin with `*`.
+    /// Without it, no CORS headers are sent.
+    #[arg(
+        long = "cors-origin",
+        env = "RPCPROXY_CORS_ORIGINS",
+        value_delimiter = ','
+    )]
+    pub cors_origins: Vec<String>,
+
+    /// Number of recent calls kept for `/admin/recent`; 0 disables it.
+    #[arg(long, env = "RPCPROXY_RECENT_REQUESTS", default_value = "0")]
+    pub recent_requests: usize,
+
     /// Run a health check against the running instance and exit.
-    /// Connects to http://localhost:<port>/health and exits with
+    /// Connects to http://<health-host>:<port>/<health-endpoint> and exits with
     /// code 0 if healthy, code 1 otherwise.
     #[arg(long)]
     pub health: bool,
+
+    /// Host probed by `--health`. Set it to monitor a remote proxy instance.
+    #[arg(long, env = "RPCPROXY_HEALTH_HOST", default_value = "127.0.0.1")]
+    pub health_host: String,
+
+    /// Endpoint probed by `--health`. `readiness` sends `--token` as a Bearer
+    /// header when one is set.
+    #[arg(
+        long,
+        env = "RPCPROXY_HEALTH_ENDPOINT",
+        value_enum,
+        default_value = "health"
+    )]
+    pub health_endpoint: HealthEndpoint,
+
+    /// Probe every configured target once for its latest block and chain id,
+    /// print the results, and exit with code 0 only if all of them answered.
+    /// Useful as a smoke test before deploying a new target list.
+    #[arg(long)]
+    pub check_targets: bool,
+
+    /// Probe every target once at startup and exit with an error, before
+    /// binding, unless at least one returns its latest block.
+    #[arg(long, env = "RPCPROXY_REQUIRE_BACKEND_ON_START")]
+    pub require_backend_on_start: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthEndpoint {
+    Health,
+    Readiness,
+}
+
+impl HealthEndpoint {
+    pub fn path(self) -> &'static str {
+        match self {
+            HealthEndpoint::Health => "/health",
+            HealthEndpoint::Readiness => "/readiness",
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
 }
 
 pub fn validate_token(token: &str) -> Result<(), String> {
@@ -69,3 +517,198 @@ pub fn validate_token(token: &str) -> Result<(), String> {
     }
     Ok(())
 }
+
+/// Checks that every target URL is an absolute `http` or `https` URL, so a
+/// typo such as `localhost:8545` fails at startup instead of producing a
+/// backend that never answers. Lists every bad entry, with secrets masked.
+pub fn validate_targets(targets: &[Target]) -> Result<(), String> {
+    let problems: Vec<String> = targets
+        .iter()
+        .filter_map(|target| {
+            let reason = match reqwest::Url::parse(&target.url) {
+                Ok(url) if !matches!(url.scheme(), "http" | "https") => format!(
+                    "unsupported scheme '{}' (expected http:// or https://)",
+                    url.scheme()
+                ),
+                Ok(url) if url.host_str().is_none_or(str::is_empty) => "missing host".to_string(),
+                Ok(_) => return None,
+                Err(e) => e.to_string(),
+            };
+            Some(format!("'{}': {reason}", sanitize_url(&target.url)))
+        })
+        .collect();
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems.join("; "))
+    }
+}
+
+/// Reads the targets listed in a `--targets-file`, one per line in priority
+/// order, skipping blank lines and `#` comments. Each is parsed and validated
+/// like a `--targets` entry; errors name the offending line.
+pub fn read_targets_file(path: &Path) -> Result<Vec<Target>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut targets = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let target = parse_target(line)
+            .and_then(|target| validate_targets(std::slice::from_ref(&target)).map(|_| target))
+            .map_err(|e| format!("{} line {}: {e}", path.display(), n + 1))?;
+        targets.push(target);
+    }
+    Ok(targets)
+}
+
+/// Parses a `--targets` entry of the form `url[;header=Name:value][;tags=a+b]...`.
+pub fn parse_target(spec: &str) -> Result<Target, String> {
+    let mut parts = spec.split(';');
+    let url = parts.next().unwrap_or_default().trim();
+    if url.is_empty() {
+        return Err(format!("target '{spec}' has an empty URL"));
+    }
+
+    let mut target = Target::from(url.to_string());
+    for option in parts {
+        let (key, value) = option
+            .split_once('=')
+            .ok_or_else(|| format!("target option '{option}' must be key=value"))?;
+        match key.trim() {
+            "header" => {
+                let (name, value) = value
+                    .split_once(':')
+                    .ok_or_else(|| format!("header '{value}' must be Name:value"))?;
+                let name = name.trim();
+                if name.is_empty() {
+                    return Err(format!("header '{value}' has an empty name"));
+                }
+                target.headers.push(TargetHeader {
+                    name: name.to_string(),
+                    value: value.trim().to_string(),
+                });
+            }
+            "tags" => {
+                for tag in value.split('+').map(str::trim) {
+                    if tag.is_empty() {
+                        return Err(format!("tags '{value}' has an empty tag"));
+                    }
+                    target.tags.push(tag.to_string());
+                }
+            }
+            other => return Err(format!("unknown target option '{other}'")),
+        }
+    }
+    Ok(target)
+}
+
+/// Parses a `--strip-field` entry of the form `method:path.to.field`.
+pub fn parse_strip_field(spec: &str) -> Result<StripRule, String> {
+    let (method, path) = spec
+        .split_once(':')
+        .ok_or_else(|| format!("strip field '{spec}' must be method:path"))?;
+    let method = method.trim();
+    let path: Vec<String> = path.trim().split('.').map(str::to_string).collect();
+    if method.is_empty() || path.iter().any(|p| p.is_empty()) {
+        return Err(format!(
+            "strip field '{spec}' has an empty method or path segment"
+        ));
+    }
+    Ok(StripRule {
+        method: method.to_string(),
+        path,
+    })
+}
+
+/// Parses a `--method-alias` entry of the form `old=new`.
+pub fn parse_method_alias(spec: &str) -> Result<(String, String), String> {
+    let (old, new) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("method alias '{spec}' must be old=new"))?;
+    let (old, new) = (old.trim(), new.trim());
+    if old.is_empty() || new.is_empty() {
+        return Err(format!("method alias '{spec}' has an empty method name"));
+    }
+    Ok((old.to_string(), new.to_string()))
+}
+
+/// Parses a `--route` entry of the form `method=index`.
+pub fn parse_route(spec: &str) -> Result<(String, usize), String> {
+    let (method, index) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("route '{spec}' must be method=index"))?;
+    let method = method.trim();
+    if method.is_empty() {
+        return Err(format!("route '{spec}' has an empty method name"));
+    }
+    let index = index
+        .trim()
+        .parse()
+        .map_err(|_| format!("route '{spec}' has an invalid backend index"))?;
+    Ok((method.to_string(), index))
+}
+
+/// Parses a `--method-timeout` entry of the form `method=secs`.
+pub fn parse_method_timeout(spec: &str) -> Result<(String, Duration), String> {
+    let (method, secs) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("method timeout '{spec}' must be method=secs"))?;
+    let method = method.trim();
+    if method.is_empty() {
+        return Err(format!("method timeout '{spec}' has an empty method name"));
+    }
+    let secs: u64 =
+        secs.trim().parse().ok().filter(|&s| s > 0).ok_or_else(|| {
+            format!("method timeout '{spec}' must be a positive number of seconds")
+        })?;
+    Ok((method.to_string(), Duration::from_secs(secs)))
+}
+
+/// Parses a `--route-tag` entry of the form `method=tag`.
+pub fn parse_route_tag(spec: &str) -> Result<(String, String), String> {
+    let (method, tag) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("route tag '{spec}' must be method=tag"))?;
+    let (method, tag) = (method.trim(), tag.trim());
+    if method.is_empty() || tag.is_empty() {
+        return Err(format!("route tag '{spec}' has an empty method or tag"));
+    }
+    Ok((method.to_string(), tag.to_string()))
+}
+
+/// Smallest non-zero `--cache-ttl`. Shorter entries expire before they can
+/// be reused and only add insert and eviction churn.
+pub const MIN_CACHE_TTL_MS: u64 = 100;
+
+fn parse_cache_ttl(s: &str) -> Result<u64, String> {
+    let value: u64 = s.parse().map_err(|e| format!("{e}"))?;
+    if value != 0 && value < MIN_CACHE_TTL_MS {
+        return Err(format!(
+            "{value} ms is below the minimum of {MIN_CACHE_TTL_MS} ms (use 0 to disable caching)"
+        ));
+    }
+    Ok(value)
+}
+
+/// Parses `--upstream-proxy`. The scheme is required so a typo can't
+/// silently fall back to a plain HTTP proxy.
+pub fn parse_upstream_proxy(s: &str) -> Result<reqwest::Proxy, String> {
+    let url = reqwest::Url::parse(s).map_err(|e| format!("'{}': {e}", sanitize_url(s)))?;
+    if !matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") {
+        return Err(format!(
+            "'{}' must use http, https, socks5, or socks5h",
+            sanitize_url(s)
+        ));
+    }
+    reqwest::Proxy::all(url).map_err(|e| format!("'{}': {e}", sanitize_url(s)))
+}
+
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    if !(0.0..=1.0).contains(&value) {
+        return Err(format!("{value} is not between 0 and 1"));
+    }
+    Ok(value)
+}
@@ -1,30 +1,96 @@
 use std::fmt;
+use std::time::Duration;
+
+use serde::Serialize;
 
 #[derive(Debug)]
 pub enum RpcProxyError {
-    /// All upstream backends failed to handle the request
-    AllUpstreamsFailed,
+    /// All upstream backends failed to handle the request; carries each
+    /// attempt that was made, in order
+    AllUpstreamsFailed { attempts: Vec<FailedAttempt> },
+    /// Upstream answered HTTP 429, or (from the manager) every backend is
+    /// rate-limited; carries the `Retry-After` delay when one is known
+    RateLimited { retry_after: Option<Duration> },
+    /// The client's `X-Request-Timeout-Ms` budget ran out before an
+    /// upstream answered
+    DeadlineExceeded,
     /// A single upstream request failed
     UpstreamRequest(String),
     /// HTTP status error from upstream
     UpstreamHttp(u16),
+    /// Upstream answered with a declared content type that isn't JSON, such
+    /// as the HTML of a maintenance page
+    NotJson { status: u16, content_type: String },
     /// Failed to serialize/deserialize JSON
     Json(serde_json::Error),
     /// Failed to read response body
     BodyRead(String),
     /// Health probe failed
     HealthProbe(String),
+    /// Upstream body is not a usable JSON-RPC response; carries the reason
+    /// and the start of the body for diagnosis
+    MalformedResponse(String),
+    /// Upstream answered with a response whose id does not match the request
+    IdMismatch {
+        expected: serde_json::Value,
+        actual: serde_json::Value,
+    },
+    /// Upstream body exceeded `--max-response-bytes` and was abandoned
+    ResponseTooLarge { limit: usize },
+    /// No two backends gave the same answer to a `--quorum-method` call;
+    /// carries how many answered at all
+    NoQuorum { answers: usize },
+    /// An `eth_getLogs` span is too wide to split; carries the widest span
+    /// that would be accepted
+    RangeTooWide { max_blocks: u64 },
+}
+
+/// One backend's failure within a request, reported in `error.data` with
+/// `--verbose-errors`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedAttempt {
+    /// The backend's display URL, with secrets masked unless `--show-full-urls`.
+    pub backend: String,
+    pub error: String,
 }
 
 impl fmt::Display for RpcProxyError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::AllUpstreamsFailed => write!(f, "all upstream backends failed"),
+            Self::AllUpstreamsFailed { .. } => write!(f, "all upstream backends failed"),
+            Self::RateLimited {
+                retry_after: Some(delay),
+            } => write!(f, "upstream rate limited, retry after {}s", delay.as_secs()),
+            Self::RateLimited { retry_after: None } => write!(f, "upstream rate limited"),
+            Self::DeadlineExceeded => write!(f, "request deadline exceeded"),
             Self::UpstreamRequest(e) => write!(f, "upstream request failed: {e}"),
             Self::UpstreamHttp(status) => write!(f, "upstream HTTP {status}"),
+            Self::NotJson {
+                status,
+                content_type,
+            } => write!(
+                f,
+                "upstream HTTP {status} with non-JSON content-type '{content_type}'"
+            ),
             Self::Json(e) => write!(f, "JSON error: {e}"),
             Self::BodyRead(e) => write!(f, "body read error: {e}"),
             Self::HealthProbe(e) => write!(f, "health probe failed: {e}"),
+            Self::MalformedResponse(e) => write!(f, "malformed upstream response: {e}"),
+            Self::IdMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "upstream response id {actual} does not match request id {expected}"
+                )
+            }
+            Self::ResponseTooLarge { limit } => {
+                write!(f, "upstream response exceeded {limit} bytes")
+            }
+            Self::NoQuorum { answers } => {
+                write!(f, "no two backends agreed ({answers} answered)")
+            }
+            Self::RangeTooWide { max_blocks } => {
+                write!(f, "eth_getLogs span exceeds {max_blocks} blocks")
+            }
         }
     }
 }
@@ -0,0 +1,35 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of RPC requests being handled right now, shared by clones.
+#[derive(Debug, Clone, Default)]
+pub struct ActiveRequests {
+    count: Arc<AtomicUsize>,
+}
+
+impl ActiveRequests {
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Counts a request as active until the returned guard is dropped, so
+    /// it is released however the handler returns.
+    pub fn start(&self) -> ActiveGuard {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        ActiveGuard {
+            count: self.count.clone(),
+        }
+    }
+}
+
+/// Holds one request in `ActiveRequests`.
+#[derive(Debug)]
+pub struct ActiveGuard {
+    count: Arc<AtomicUsize>,
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,79 @@
+use axum::extract::{RawQuery, State};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Json, Response};
+use tracing::warn;
+
+use super::AppState;
+use super::auth::{check_bearer_token, unauthorized_response};
+
+/// Keys listed when the request doesn't set `limit`.
+const DEFAULT_KEY_LIMIT: usize = 100;
+/// Most keys listed in one response, whatever `limit` asks for.
+const MAX_KEY_LIMIT: usize = 1000;
+
+/// Lists cached keys with their remaining TTL and size, for troubleshooting.
+/// Paginated with `?offset=` and `?limit=`, in key order.
+pub async fn cache_keys_handler(
+    State(state): State<AppState>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+) -> Response {
+    if !check_bearer_token(&state, &headers) {
+        warn!("unauthorized admin request (missing or bad token)");
+        return unauthorized_response(&state);
+    }
+
+    let offset = query_param(query.as_deref(), "offset").unwrap_or(0);
+    let limit = query_param(query.as_deref(), "limit")
+        .unwrap_or(DEFAULT_KEY_LIMIT)
+        .min(MAX_KEY_LIMIT);
+    let (total, keys) = state.cache.keys(offset, limit);
+
+    Json(serde_json::json!({
+        "total": total,
+        "offset": offset,
+        "limit": limit,
+        "keys": keys,
+    }))
+    .into_response()
+}
+
+/// The last calls handled, newest first, with their latency, cache status,
+/// backend, and any error returned to the client.
+pub async fn recent_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if !check_bearer_token(&state, &headers) {
+        warn!("unauthorized admin request (missing or bad token)");
+        return unauthorized_response(&state);
+    }
+
+    Json(serde_json::json!({
+        "capacity": state.recent.capacity(),
+        "calls": state.recent.newest_first(),
+    }))
+    .into_response()
+}
+
+/// The backend URLs in priority order, for tooling that only needs to know
+/// what the proxy forwards to.
+pub async fn targets_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if !check_bearer_token(&state, &headers) {
+        warn!("unauthorized admin request (missing or bad token)");
+        return unauthorized_response(&state);
+    }
+
+    Json(serde_json::json!({
+        "targets": state.upstream.backend_urls(),
+    }))
+    .into_response()
+}
+
+/// A numeric query parameter. Missing or malformed values are ignored.
+fn query_param(query: Option<&str>, name: &str) -> Option<usize> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)?
+        .1
+        .parse()
+        .ok()
+}
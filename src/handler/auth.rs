@@ -1,8 +1,13 @@
-use axum::http::HeaderMap;
+use axum::http::{HeaderMap, HeaderValue, header};
+use axum::response::{IntoResponse, Response};
+
+use crate::jsonrpc::JsonRpcResponse;
 
 use super::AppState;
 
 /// Returns true if no token is configured or if the Authorization header matches.
+/// Used by the RPC endpoints as an alternative to the path token, and by the
+/// status endpoints as the only option.
 pub fn check_bearer_token(state: &AppState, headers: &HeaderMap) -> bool {
     let Some(expected) = &state.token else {
         return true;
@@ -10,7 +15,58 @@ pub fn check_bearer_token(state: &AppState, headers: &HeaderMap) -> bool {
     headers
         .get("authorization")
         .and_then(|v| v.to_str().ok())
-        .and_then(|v| v.strip_prefix("Bearer "))
-        .map(|t| t == expected.as_str())
+        .and_then(bearer_credentials)
+        .map(|t| tokens_match(t, expected))
         .unwrap_or(false)
 }
+
+/// Returns true if query tokens are allowed and `?token=` matches. Tokens are
+/// limited to URL-safe characters, so the value is compared as sent.
+pub fn check_query_token(state: &AppState, query: Option<&str>) -> bool {
+    let Some(expected) = &state.token else {
+        return true;
+    };
+    if !state.options.allow_query_token {
+        return false;
+    }
+    query
+        .into_iter()
+        .flat_map(|q| q.split('&'))
+        .filter_map(|pair| pair.strip_prefix("token="))
+        .any(|t| tokens_match(t, expected))
+}
+
+/// Compares a presented token with the configured one without stopping at
+/// the first differing byte, so response timing doesn't reveal how much of
+/// a guess was right.
+pub fn tokens_match(presented: &str, expected: &str) -> bool {
+    let (a, b) = (presented.as_bytes(), expected.as_bytes());
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The token from an `Authorization: Bearer <token>` value. The scheme name
+/// is case-insensitive (RFC 7235), so `bearer` from some HTTP clients works too.
+fn bearer_credentials(value: &str) -> Option<&str> {
+    let (scheme, token) = value.split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then(|| token.trim())
+        .filter(|t| !t.is_empty())
+}
+
+/// The answer to a request without a valid token, the same on the RPC and
+/// status endpoints: a JSON-RPC `-32000 Unauthorized` error with a null id,
+/// sent with the configured `unauthorized_status`.
+pub fn unauthorized_response(state: &AppState) -> Response {
+    let resp = JsonRpcResponse::error(serde_json::Value::Null, -32000, "Unauthorized");
+    let body = serde_json::to_vec(&resp).expect("JSON-RPC responses always serialize");
+    (
+        state.options.unauthorized_status,
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        )],
+        body,
+    )
+        .into_response()
+}
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use serde_json::Value;
+use tokio::task::JoinSet;
+
+use crate::error::RpcProxyError;
+use crate::jsonrpc::{JsonRpcRequest, JsonRpcResponse};
+use crate::upstream::{TargetHeader, UpstreamManager};
+
+/// Sub-queries of one split `eth_getLogs` call that are in flight at once.
+const MAX_CONCURRENT_SUBQUERIES: usize = 8;
+
+/// Most sub-queries one `eth_getLogs` call may be split into; wider spans
+/// are refused instead of fanned out.
+const MAX_SUBQUERIES: u64 = 1000;
+
+/// Splits an `eth_getLogs` call whose block span exceeds `max_range` into
+/// consecutive sub-queries of at most `max_range` blocks each, in ascending order.
+/// A `toBlock` of `latest` is passed on as the tag in the last sub-query, since
+/// the known head may already be behind.
+///
+/// Returns `None` when the request should be forwarded unchanged: it is not
+/// `eth_getLogs`, it filters by `blockHash`, it uses a tag that can't be
+/// resolved (`pending`, `safe`, `finalized`, or `latest` with no known head),
+/// or its span already fits. A span that would need more than
+/// `MAX_SUBQUERIES` sub-queries is an error.
+pub fn split_request(
+    request: &JsonRpcRequest,
+    latest_block: Option<u64>,
+    max_range: u64,
+) -> Option<Result<Vec<JsonRpcRequest>, RpcProxyError>> {
+    if request.method != "eth_getLogs" || max_range == 0 {
+        return None;
+    }
+    let filter = request.params.as_array()?.first()?.as_object()?;
+    if filter.contains_key("blockHash") {
+        return None;
+    }
+
+    let from = resolve_block(filter.get("fromBlock"), latest_block)?;
+    let to = resolve_block(filter.get("toBlock"), latest_block)?;
+    if from > to || to - from < max_range {
+        return None;
+    }
+    if (to - from) / max_range >= MAX_SUBQUERIES {
+        return Some(Err(RpcProxyError::RangeTooWide {
+            max_blocks: max_range.saturating_mul(MAX_SUBQUERIES),
+        }));
+    }
+    let to_latest = matches!(
+        filter.get("toBlock").map(|p| p.as_str()),
+        None | Some(Some("latest"))
+    );
+
+    let mut parts = Vec::new();
+    let mut start = from;
+    loop {
+        let end = start.saturating_add(max_range - 1).min(to);
+        let mut sub = filter.clone();
+        sub.insert("fromBlock".into(), Value::String(format!("{start:#x}")));
+        if !(end == to && to_latest) {
+            sub.insert("toBlock".into(), Value::String(format!("{end:#x}")));
+        }
+        parts.push(JsonRpcRequest {
+            jsonrpc: request.jsonrpc.clone(),
+            method: request.method.clone(),
+            params: Value::Array(vec![Value::Object(sub)]),
+            id: request.id.clone(),
+        });
+        if end == to {
+            break;
+        }
+        start = end + 1;
+    }
+    Some(Ok(parts))
+}
+
+/// Resolves a block parameter to a number. A missing bound means `latest`.
+fn resolve_block(param: Option<&Value>, latest_block: Option<u64>) -> Option<u64> {
+    match param.map(|p| p.as_str()) {
+        None | Some(Some("latest")) => latest_block,
+        Some(Some("earliest")) => Some(0),
+        Some(Some(s)) => u64::from_str_radix(s.strip_prefix("0x")?, 16).ok(),
+        Some(None) => None,
+    }
+}
+
+/// Sends the sub-queries from `split_request` and concatenates their logs in
+/// block order. The first sub-query to return a JSON-RPC error fails the call
+/// with that error, since a partial log set would be silently wrong.
+/// `forwarded` client headers go with every sub-query.
+pub async fn send_split(
+    upstream: &Arc<UpstreamManager>,
+    parts: Vec<JsonRpcRequest>,
+    forwarded: &[TargetHeader],
+) -> Result<JsonRpcResponse, RpcProxyError> {
+    let forwarded: Arc<[TargetHeader]> = forwarded.into();
+    let mut responses: Vec<Option<JsonRpcResponse>> = vec![None; parts.len()];
+    let mut pending = parts.into_iter().enumerate();
+    let mut tasks = JoinSet::new();
+
+    loop {
+        while tasks.len() < MAX_CONCURRENT_SUBQUERIES
+            && let Some((i, part)) = pending.next()
+        {
+            let upstream = upstream.clone();
+            let forwarded = forwarded.clone();
+            tasks.spawn(async move {
+                let result = upstream
+                    .send_request_with_affinity(&part, None, &forwarded)
+                    .await;
+                (i, result)
+            });
+        }
+        let Some(joined) = tasks.join_next().await else {
+            break;
+        };
+        let (i, result) = joined.map_err(|e| RpcProxyError::UpstreamRequest(e.to_string()))?;
+        let response = result?;
+        if response.error.is_some() {
+            return Ok(response);
+        }
+        responses[i] = Some(response);
+    }
+
+    let mut merged = Vec::new();
+    let mut first = None;
+    for response in responses.into_iter().flatten() {
+        match response.result.as_ref() {
+            Some(Value::Array(logs)) => merged.extend(logs.iter().cloned()),
+            _ => {
+                return Err(RpcProxyError::MalformedResponse(
+                    "eth_getLogs result is not an array".into(),
+                ));
+            }
+        }
+        first.get_or_insert(response);
+    }
+
+    let mut response = first.ok_or(RpcProxyError::AllUpstreamsFailed {
+        attempts: Vec::new(),
+    })?;
+    response.result = Some(Value::Array(merged));
+    Ok(response)
+}
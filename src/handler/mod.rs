@@ -1,15 +1,128 @@
+pub mod active;
+pub mod admin;
 mod auth;
+pub mod getlogs;
+pub mod recent;
 pub mod rpc;
 pub mod status;
+pub mod transform;
 
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::{HeaderName, StatusCode};
 
 use crate::cache::RpcCache;
 use crate::upstream::UpstreamManager;
 
+use active::ActiveRequests;
+use recent::RecentCalls;
+use transform::StripRule;
+
 #[derive(Clone)]
 pub struct AppState {
     pub upstream: Arc<UpstreamManager>,
     pub cache: RpcCache,
     pub token: Option<String>,
+    pub options: HandlerOptions,
+    /// The last calls handled, for `/admin/recent`.
+    pub recent: RecentCalls,
+    /// RPC requests in progress, for `/status`.
+    pub active: ActiveRequests,
+}
+
+/// Optional request-handling behavior. The defaults match the proxy's
+/// behavior when none of the corresponding flags are set.
+#[derive(Debug, Clone)]
+pub struct HandlerOptions {
+    /// Emit `Cache-Control` headers on RPC responses so a caching layer in
+    /// front of the proxy can reuse them.
+    pub cache_control_headers: bool,
+    /// Number of healthy backends with a known block required for `/health`
+    /// and `/readiness` to report ready.
+    pub min_healthy_backends: usize,
+    /// Readiness fails if no backend's block has advanced within this window.
+    pub max_block_age: Option<Duration>,
+    /// Fields removed from specific methods' results before caching.
+    pub strip_fields: Vec<StripRule>,
+    /// Deprecated or vendor method names rewritten to their canonical name
+    /// before caching and forwarding.
+    pub method_aliases: HashMap<String, String>,
+    /// Split `eth_getLogs` calls spanning more than this many blocks into
+    /// sub-queries and merge their results.
+    pub split_getlogs: Option<u64>,
+    /// Rewrite `latest` to the best block number, if a health probe reported
+    /// it within this window, so the call is cached against that block.
+    pub resolve_latest: Option<Duration>,
+    /// Pass non-cacheable upstream responses larger than this many bytes
+    /// straight through to the client instead of buffering them.
+    pub stream_threshold_bytes: Option<usize>,
+    /// JSON-RPC error code returned when every backend failed.
+    pub upstream_down_code: i64,
+    /// HTTP status for a single request that failed on every backend.
+    /// `None` keeps 200, as for any other JSON-RPC error.
+    pub upstream_down_status: Option<StatusCode>,
+    /// HTTP status for a single request answered with a JSON-RPC error
+    /// returned by a backend. Errors the proxy produces itself keep 200, as
+    /// does `None`.
+    pub error_status: Option<StatusCode>,
+    /// Requests carrying the same value in this header are routed to the
+    /// same backend.
+    pub sticky_header: Option<HeaderName>,
+    /// Put the backends tried, their errors, and the elapsed time in
+    /// `error.data` when a call fails upstream.
+    pub verbose_errors: bool,
+    /// Accept requests with a missing or `"1.0"` `jsonrpc` field, forwarding
+    /// them as `"2.0"`.
+    pub lenient_jsonrpc: bool,
+    /// Forward omitted or `null` params as `[]`.
+    pub normalize_params: bool,
+    /// Refuse methods that submit transactions or change node state.
+    pub read_only: bool,
+    /// Pass the client's W3C trace context headers (`traceparent`,
+    /// `tracestate`) on to the backends.
+    pub propagate_trace_headers: bool,
+    /// HTTP status sent with the `Unauthorized` error on protected endpoints.
+    pub unauthorized_status: StatusCode,
+    /// Accept the token as a `?token=` query parameter on the RPC endpoints.
+    pub allow_query_token: bool,
+    /// Send a weak `ETag` with cached single-call responses and answer a
+    /// matching `If-None-Match` with `304 Not Modified`.
+    pub etag: bool,
+    /// Send an uncached batch upstream as one batch instead of one call per
+    /// element, falling back to per-element calls if that fails.
+    pub upstream_batching: bool,
+    /// Send the upstream calls of one batch to the backend that answered
+    /// its first one, so they all see the same chain head.
+    pub batch_consistency: bool,
+}
+
+impl Default for HandlerOptions {
+    fn default() -> Self {
+        Self {
+            cache_control_headers: false,
+            min_healthy_backends: 1,
+            max_block_age: None,
+            strip_fields: Vec::new(),
+            method_aliases: HashMap::new(),
+            split_getlogs: None,
+            resolve_latest: None,
+            stream_threshold_bytes: None,
+            upstream_down_code: -32004,
+            upstream_down_status: None,
+            error_status: None,
+            sticky_header: None,
+            verbose_errors: false,
+            lenient_jsonrpc: false,
+            normalize_params: false,
+            read_only: false,
+            propagate_trace_headers: true,
+            unauthorized_status: StatusCode::UNAUTHORIZED,
+            allow_query_token: false,
+            etag: false,
+            upstream_batching: false,
+            batch_consistency: false,
+        }
+    }
 }
@@ -0,0 +1,94 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// How a call's answer relates to the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheStatus {
+    /// Served from a cached response.
+    Hit,
+    /// Shared the response of an identical call already in flight.
+    Coalesced,
+    /// Repeated an upstream error from the error cooldown.
+    Cooldown,
+    /// Answered without the cache, whether forwarded or rejected up front.
+    Miss,
+}
+
+/// One JSON-RPC call as recorded for `/admin/recent`.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentCall {
+    /// Unix time in seconds when the call finished.
+    pub timestamp: u64,
+    pub method: String,
+    pub latency_ms: f64,
+    pub cache: CacheStatus,
+    /// The backend that answered, if the call went upstream.
+    pub backend: Option<String>,
+    /// The JSON-RPC error the client got, as `code: message`.
+    pub error: Option<String>,
+}
+
+/// The last `capacity` calls, oldest first. With a capacity of 0 nothing is
+/// recorded.
+#[derive(Debug, Clone, Default)]
+pub struct RecentCalls {
+    capacity: usize,
+    calls: Arc<Mutex<VecDeque<RecentCall>>>,
+}
+
+impl RecentCalls {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            calls: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Records a call that just finished, dropping the oldest one once full.
+    pub fn record(
+        &self,
+        method: String,
+        latency: Duration,
+        cache: CacheStatus,
+        backend: Option<String>,
+        error: Option<String>,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let call = RecentCall {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            method,
+            latency_ms: latency.as_secs_f64() * 1000.0,
+            cache,
+            backend,
+            error,
+        };
+        // A panic elsewhere while holding the lock leaves the buffer usable.
+        let mut calls = self.calls.lock().unwrap_or_else(|e| e.into_inner());
+        if calls.len() == self.capacity {
+            calls.pop_front();
+        }
+        calls.push_back(call);
+    }
+
+    /// The recorded calls, newest first.
+    pub fn newest_first(&self) -> Vec<RecentCall> {
+        let calls = self.calls.lock().unwrap_or_else(|e| e.into_inner());
+        calls.iter().rev().cloned().collect()
+    }
+}
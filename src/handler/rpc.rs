@@ -1,160 +1,1068 @@
 use std::sync::Arc;
+use std::time::Duration;
 
-use axum::extract::{Path, State};
-use axum::http::{HeaderMap, StatusCode};
-use axum::response::{IntoResponse, Json};
-use tracing::{error, warn};
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+use axum::body::Body;
+use axum::extract::{Path, RawQuery, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use tracing::{Instrument, debug, error, info, warn};
 
 use crate::cache::policy as cache_policy;
-use crate::jsonrpc::{JsonRpcBody, JsonRpcRequest, JsonRpcResponse};
+use crate::error::RpcProxyError;
+use crate::jsonrpc::{JsonRpcBody, JsonRpcRequest, JsonRpcResponse, SerializedResponse};
+use crate::telemetry;
+use crate::upstream::{ByteStream, TargetHeader, UpstreamReply, pin_backend, track_backend};
 
 use super::AppState;
-use super::auth::check_bearer_token;
+use super::auth::{check_bearer_token, check_query_token, tokens_match, unauthorized_response};
+use super::getlogs;
+use super::recent::CacheStatus;
+use super::transform::apply_strip_rules;
+
+/// Request header with the client's total time budget in milliseconds.
+const REQUEST_TIMEOUT_HEADER: &str = "x-request-timeout-ms";
+
+/// Request header that, set to `true`, skips the cache read for the request.
+const CACHE_BYPASS_HEADER: &str = "x-cache-bypass";
+
+/// W3C trace context headers passed through to the backends.
+const TRACE_HEADERS: &[&str] = &["traceparent", "tracestate"];
 
 /// RPC handler for token-authenticated path: POST /<token>
 pub async fn token_rpc_handler(
     State(state): State<AppState>,
     Path(path_token): Path<String>,
+    RawQuery(query): RawQuery,
     headers: HeaderMap,
     body: String,
 ) -> impl IntoResponse {
     if let Some(expected_token) = &state.token {
-        let path_valid = path_token == *expected_token;
+        let path_valid = tokens_match(&path_token, expected_token);
         let header_valid = check_bearer_token(&state, &headers);
-        if !path_valid && !header_valid {
-            warn!("unauthorized RPC request (bad token path and no valid bearer)");
-            return (
-                StatusCode::UNAUTHORIZED,
-                Json(
-                    serde_json::to_value(JsonRpcResponse::error(
-                        serde_json::Value::Null,
-                        -32000,
-                        "Unauthorized",
-                    ))
-                    .unwrap(),
-                ),
-            );
+        let query_valid = check_query_token(&state, query.as_deref());
+        if !path_valid && !header_valid && !query_valid {
+            warn!("unauthorized RPC request (no valid path, bearer or query token)");
+            return unauthorized_response(&state);
         }
     }
-    dispatch_rpc(&state, body).await
+    let affinity = sticky_key(&state, &headers);
+    let forwarded = trace_headers(&state, &headers);
+    dispatch_rpc(
+        &state,
+        body,
+        affinity,
+        &forwarded,
+        request_deadline(&headers),
+        if_none_match(&headers),
+        cache_bypass(&headers),
+    )
+    .instrument(telemetry::request_span(&headers))
+    .await
 }
 
 /// RPC handler for open access: POST /
 pub async fn open_rpc_handler(
     State(state): State<AppState>,
+    RawQuery(query): RawQuery,
     headers: HeaderMap,
     body: String,
 ) -> impl IntoResponse {
-    if state.token.is_some() && !check_bearer_token(&state, &headers) {
+    if state.token.is_some()
+        && !check_bearer_token(&state, &headers)
+        && !check_query_token(&state, query.as_deref())
+    {
         warn!("unauthorized RPC request (missing or bad bearer token)");
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(
-                serde_json::to_value(JsonRpcResponse::error(
-                    serde_json::Value::Null,
-                    -32000,
-                    "Unauthorized",
-                ))
-                .unwrap(),
-            ),
-        );
+        return unauthorized_response(&state);
     }
-    dispatch_rpc(&state, body).await
+    let affinity = sticky_key(&state, &headers);
+    let forwarded = trace_headers(&state, &headers);
+    dispatch_rpc(
+        &state,
+        body,
+        affinity,
+        &forwarded,
+        request_deadline(&headers),
+        if_none_match(&headers),
+        cache_bypass(&headers),
+    )
+    .instrument(telemetry::request_span(&headers))
+    .await
 }
 
-async fn dispatch_rpc(state: &AppState, body: String) -> (StatusCode, Json<serde_json::Value>) {
+async fn dispatch_rpc(
+    state: &AppState,
+    body: String,
+    affinity: Option<&str>,
+    forwarded: &[TargetHeader],
+    deadline: Option<Instant>,
+    if_none_match: Option<&str>,
+    bypass_cache: bool,
+) -> Response {
+    let _active = state.active.start();
     let parsed = match serde_json::from_str::<JsonRpcBody>(&body) {
         Ok(parsed) => parsed,
         Err(_) => {
-            let resp = JsonRpcResponse::parse_error();
-            return (StatusCode::OK, Json(serde_json::to_value(resp).unwrap()));
+            let body = serde_json::to_vec(&JsonRpcResponse::parse_error())
+                .expect("JSON-RPC responses always serialize");
+            return raw_json(StatusCode::OK, body);
         }
     };
 
     match parsed {
         JsonRpcBody::Single(request) => {
-            let resp = handle_single_request(state, request).await;
-            (StatusCode::OK, Json(serde_json::to_value(resp).unwrap()))
+            let stream_threshold = state.options.stream_threshold_bytes;
+            let span = telemetry::call_span(&request.method);
+            let version = legacy_version(state, &request);
+            let (resp, cache_ttl) = handle_single_request(
+                state,
+                request,
+                stream_threshold,
+                affinity,
+                forwarded,
+                deadline,
+                bypass_cache,
+            )
+            .instrument(span)
+            .await;
+            let resp = match version {
+                Some(version) => resp.with_jsonrpc(version),
+                None => resp,
+            };
+            let response = match resp {
+                Reply::Stream(stream) => (
+                    [(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static("application/json"),
+                    )],
+                    Body::from_stream(stream),
+                )
+                    .into_response(),
+                Reply::UpstreamFailure {
+                    response,
+                    status,
+                    retry_after,
+                } => {
+                    let body =
+                        serde_json::to_vec(&response).expect("JSON-RPC responses always serialize");
+                    let mut response = raw_json(status.unwrap_or(StatusCode::OK), body);
+                    if let Some(delay) = retry_after {
+                        response
+                            .headers_mut()
+                            .insert(header::RETRY_AFTER, HeaderValue::from(delay.as_secs()));
+                    }
+                    response
+                }
+                reply => {
+                    let etag = state.options.etag.then(|| reply.etag()).flatten();
+                    if let Some(etag) = &etag
+                        && if_none_match.is_some_and(|tags| etag_matches(tags, etag))
+                    {
+                        not_modified(etag)
+                    } else {
+                        let status = match state.options.error_status {
+                            Some(status) if reply.is_upstream_error() => status,
+                            _ => StatusCode::OK,
+                        };
+                        let mut body = Vec::new();
+                        reply.write_to(&mut body);
+                        let mut response = raw_json(status, body);
+                        if let Some(etag) = etag {
+                            response.headers_mut().insert(
+                                header::ETAG,
+                                HeaderValue::from_str(&etag).expect("ETag is always valid"),
+                            );
+                        }
+                        response
+                    }
+                }
+            };
+            with_cache_control(state, cache_ttl, response)
         }
         JsonRpcBody::Batch(requests) => {
-            let mut responses = Vec::with_capacity(requests.len());
-            for request in requests {
-                let resp = handle_single_request(state, request).await;
+            let versions: Vec<_> = requests.iter().map(|r| legacy_version(state, r)).collect();
+            let batched = forward_batch(
+                state,
+                &requests,
+                affinity,
+                forwarded,
+                deadline,
+                bypass_cache,
+            )
+            .await;
+            let replies = match batched {
+                Some(replies) => replies,
+                None => {
+                    let per_call = async {
+                        let mut replies = Vec::with_capacity(requests.len());
+                        for request in requests {
+                            // Batch elements are never streamed; they're written into one array.
+                            let span = telemetry::call_span(&request.method);
+                            let reply = handle_single_request(
+                                state,
+                                request,
+                                None,
+                                affinity,
+                                forwarded,
+                                deadline,
+                                bypass_cache,
+                            )
+                            .instrument(span)
+                            .await;
+                            replies.push(reply);
+                        }
+                        replies
+                    };
+                    if state.options.batch_consistency {
+                        pin_backend(per_call).await
+                    } else {
+                        per_call.await
+                    }
+                }
+            };
+
+            let mut responses = Vec::with_capacity(replies.len());
+            // A batch is only as cacheable as its shortest-lived element.
+            let mut batch_ttl: Option<Duration> = None;
+            let mut all_cached = true;
+            for ((resp, cache_ttl), version) in replies.into_iter().zip(versions) {
+                let resp = match version {
+                    Some(version) => resp.with_jsonrpc(version),
+                    None => resp,
+                };
+                match cache_ttl {
+                    Some(ttl) => batch_ttl = Some(batch_ttl.map_or(ttl, |t| t.min(ttl))),
+                    None => all_cached = false,
+                }
                 responses.push(resp);
             }
-            (
-                StatusCode::OK,
-                Json(serde_json::to_value(responses).unwrap()),
-            )
+            let cache_ttl = if all_cached { batch_ttl } else { None };
+            let mut body = vec![b'['];
+            for (i, resp) in responses.iter().enumerate() {
+                if i > 0 {
+                    body.push(b',');
+                }
+                resp.write_to(&mut body);
+            }
+            body.push(b']');
+            with_cache_control(state, cache_ttl, raw_json(StatusCode::OK, body))
         }
     }
 }
 
-async fn handle_single_request(state: &AppState, request: JsonRpcRequest) -> JsonRpcResponse {
-    if !request.is_valid() {
-        return JsonRpcResponse::invalid_request(request.id);
+/// The version a lenient-mode `"1.0"` request's response should carry, so
+/// strict legacy clients see their own version echoed. `None` keeps `"2.0"`.
+fn legacy_version(state: &AppState, request: &JsonRpcRequest) -> Option<&'static str> {
+    (state.options.lenient_jsonrpc && request.jsonrpc == "1.0").then_some("1.0")
+}
+
+/// Whether an `If-None-Match` value lists `etag`, using weak comparison.
+/// Only concrete tags count: `*` would match any cached reply, whatever the
+/// client last saw.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag);
+    if_none_match.split(',').any(|tag| opaque(tag) == etag)
+}
+
+/// An empty `304 Not Modified` carrying the unchanged ETag.
+fn not_modified(etag: &str) -> Response {
+    (
+        StatusCode::NOT_MODIFIED,
+        [(
+            header::ETAG,
+            HeaderValue::from_str(etag).expect("ETag is always valid"),
+        )],
+    )
+        .into_response()
+}
+
+/// The sticky-session key for a request: the value of `--sticky-header`, if
+/// configured and present.
+fn sticky_key<'a>(state: &AppState, headers: &'a HeaderMap) -> Option<&'a str> {
+    let name = state.options.sticky_header.as_ref()?;
+    headers.get(name)?.to_str().ok().filter(|v| !v.is_empty())
+}
+
+/// The client's trace context headers to send upstream, unless disabled.
+fn trace_headers(state: &AppState, headers: &HeaderMap) -> Vec<TargetHeader> {
+    if !state.options.propagate_trace_headers {
+        return Vec::new();
+    }
+    TRACE_HEADERS
+        .iter()
+        .filter_map(|&name| {
+            let value = headers.get(name)?.to_str().ok()?;
+            Some(TargetHeader {
+                name: name.to_string(),
+                value: value.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The client's `If-None-Match` header, if it sent one.
+fn if_none_match(headers: &HeaderMap) -> Option<&str> {
+    headers.get(header::IF_NONE_MATCH)?.to_str().ok()
+}
+
+/// Whether the client asked for a fresh answer with `X-Cache-Bypass: true`.
+fn cache_bypass(headers: &HeaderMap) -> bool {
+    headers
+        .get(CACHE_BYPASS_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("true"))
+}
+
+/// When the client's `X-Request-Timeout-Ms` budget runs out, if it sent one.
+/// Malformed values are ignored.
+fn request_deadline(headers: &HeaderMap) -> Option<Instant> {
+    let ms: u64 = headers
+        .get(REQUEST_TIMEOUT_HEADER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Instant::now() + Duration::from_millis(ms))
+}
+
+/// Runs `fut`, giving up with `DeadlineExceeded` once `deadline` passes.
+async fn within<F: Future>(deadline: Option<Instant>, fut: F) -> Result<F::Output, RpcProxyError> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, fut)
+            .await
+            .map_err(|_| RpcProxyError::DeadlineExceeded),
+        None => Ok(fut.await),
     }
+}
+
+/// A response carrying already-serialized JSON. Serializing straight to bytes
+/// skips the intermediate `serde_json::Value` tree that `Json<Value>` needs.
+fn raw_json(status: StatusCode, body: Vec<u8>) -> Response {
+    (
+        status,
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        )],
+        body,
+    )
+        .into_response()
+}
+
+/// Adds a `Cache-Control` header when enabled: `public, max-age=<secs>` for
+/// responses served from or stored in the cache, `no-store` for everything else.
+fn with_cache_control(
+    state: &AppState,
+    cache_ttl: Option<Duration>,
+    mut response: Response,
+) -> Response {
+    if !state.options.cache_control_headers {
+        return response;
+    }
+    let value = match cache_ttl {
+        Some(ttl) => HeaderValue::from_str(&format!("public, max-age={}", ttl.as_secs()))
+            .expect("max-age header value is always valid"),
+        None => HeaderValue::from_static("no-store"),
+    };
+    response.headers_mut().insert(header::CACHE_CONTROL, value);
+    response
+}
+
+/// One call's answer. Cache hits reuse the cached response's serialized bytes
+/// (or, for coalesced waiters, the shared response) and only carry the caller's
+/// id, so a large result is neither deep-cloned nor re-serialized per request.
+enum Reply {
+    /// A response the proxy built itself, such as a protocol error.
+    Fresh(JsonRpcResponse),
+    /// A backend's response passed on without going through the cache.
+    Upstream(JsonRpcResponse),
+    Shared {
+        response: Arc<JsonRpcResponse>,
+        id: serde_json::Value,
+    },
+    Serialized {
+        body: SerializedResponse,
+        content_hash: u64,
+        id: serde_json::Value,
+    },
+    /// A large upstream body passed through unparsed.
+    Stream(ByteStream),
+    /// No backend could answer. Kept apart from `Fresh` so a single request
+    /// can be given a non-200 status and `Retry-After`; batches ignore both.
+    UpstreamFailure {
+        response: JsonRpcResponse,
+        status: Option<StatusCode>,
+        retry_after: Option<Duration>,
+    },
+}
+
+impl Reply {
+    fn write_to(&self, out: &mut Vec<u8>) {
+        let written = match self {
+            Reply::Fresh(response)
+            | Reply::Upstream(response)
+            | Reply::UpstreamFailure { response, .. } => serde_json::to_writer(&mut *out, response),
+            Reply::Shared { response, id } => {
+                serde_json::to_writer(&mut *out, &response.with_id(id))
+            }
+            Reply::Serialized { body, id, .. } => {
+                body.write_with_id(id, out);
+                Ok(())
+            }
+            Reply::Stream(_) => unreachable!("streamed replies are sent as a streaming body"),
+        };
+        written.expect("JSON-RPC responses always serialize");
+    }
+
+    /// This reply with its `jsonrpc` field set to `version`. Cached replies
+    /// become owned copies; streamed bodies pass through unchanged.
+    fn with_jsonrpc(self, version: &str) -> Reply {
+        let mut response = match self {
+            Reply::Fresh(mut response) => {
+                response.jsonrpc = version.to_string();
+                return Reply::Fresh(response);
+            }
+            Reply::Upstream(response) => response,
+            Reply::UpstreamFailure {
+                mut response,
+                status,
+                retry_after,
+            } => {
+                response.jsonrpc = version.to_string();
+                return Reply::UpstreamFailure {
+                    response,
+                    status,
+                    retry_after,
+                };
+            }
+            Reply::Shared { response, id } => JsonRpcResponse {
+                jsonrpc: String::new(),
+                result: response.result.clone(),
+                error: response.error.clone(),
+                id,
+            },
+            Reply::Serialized { .. } => {
+                let mut body = Vec::new();
+                self.write_to(&mut body);
+                // Cached bodies were parsed once already; should one still
+                // fail, keep the reply as it is rather than panic.
+                match serde_json::from_slice(&body) {
+                    Ok(response) => response,
+                    Err(_) => return self,
+                }
+            }
+            Reply::Stream(_) => return self,
+        };
+        response.jsonrpc = version.to_string();
+        Reply::Upstream(response)
+    }
+
+    /// The JSON-RPC error in this reply, as `code: message`. Cached replies
+    /// are reported as successes.
+    fn error_summary(&self) -> Option<String> {
+        let error = match self {
+            Reply::Fresh(response)
+            | Reply::Upstream(response)
+            | Reply::UpstreamFailure { response, .. } => response.error.as_ref(),
+            Reply::Shared { response, .. } => response.error.as_ref(),
+            Reply::Serialized { .. } | Reply::Stream(_) => None,
+        }?;
+        Some(format!("{}: {}", error.code, error.message))
+    }
+
+    /// Whether this reply passes on a JSON-RPC error a backend returned,
+    /// fresh or from the cache. Errors the proxy produces itself don't
+    /// count, and streamed bodies aren't inspected.
+    fn is_upstream_error(&self) -> bool {
+        match self {
+            Reply::Upstream(response) => response.error.is_some(),
+            Reply::Shared { response, .. } => response.error.is_some(),
+            Reply::Serialized { body, .. } => body.is_error(),
+            Reply::Fresh(_) | Reply::UpstreamFailure { .. } | Reply::Stream(_) => false,
+        }
+    }
+
+    /// Weak ETag for a reply that came from or went into the cache. It
+    /// ignores the id, so repeated polls for unchanged data share one tag.
+    fn etag(&self) -> Option<String> {
+        let hash = match self {
+            Reply::Serialized { content_hash, .. } => *content_hash,
+            Reply::Shared { response, .. } => SerializedResponse::new(response).content_hash(),
+            _ => return None,
+        };
+        Some(format!("W/\"{hash:016x}\""))
+    }
+}
+
+impl From<JsonRpcResponse> for Reply {
+    fn from(response: JsonRpcResponse) -> Self {
+        Reply::Fresh(response)
+    }
+}
+
+/// Handles one JSON-RPC call. Alongside the response, returns the remaining
+/// cache lifetime if the response was served from or stored in the cache.
+/// With a `stream_threshold`, large non-cacheable responses may come back as
+/// `Reply::Stream`. `affinity` pins the call to one backend (sticky sessions).
+/// Waiting on upstreams stops at `deadline`; cache hits are always served.
+/// With `bypass_cache`, the call skips the cache read and coalescing and
+/// always goes upstream, though a cacheable result still refreshes the entry.
+async fn handle_single_request(
+    state: &AppState,
+    request: JsonRpcRequest,
+    stream_threshold: Option<usize>,
+    affinity: Option<&str>,
+    forwarded: &[TargetHeader],
+    deadline: Option<Instant>,
+    bypass_cache: bool,
+) -> (Reply, Option<Duration>) {
+    let started = Instant::now();
+    let method = state.recent.is_enabled().then(|| request.method.clone());
+    let call = answer_call(
+        state,
+        request,
+        stream_threshold,
+        affinity,
+        forwarded,
+        deadline,
+        bypass_cache,
+    );
+    let ((reply, cache_ttl, cache), backend) = track_backend(call).await;
+    if let Some(method) = method {
+        let error = reply.error_summary();
+        state
+            .recent
+            .record(method, started.elapsed(), cache, backend, error);
+    }
+    (reply, cache_ttl)
+}
+
+/// `handle_single_request` without the bookkeeping, also reporting how the
+/// cache was involved.
+async fn answer_call(
+    state: &AppState,
+    request: JsonRpcRequest,
+    stream_threshold: Option<usize>,
+    affinity: Option<&str>,
+    forwarded: &[TargetHeader],
+    deadline: Option<Instant>,
+    bypass_cache: bool,
+) -> (Reply, Option<Duration>, CacheStatus) {
+    let started = Instant::now();
+    let request = match prepare_request(state, request) {
+        Ok(request) => request,
+        Err(response) => return ((*response).into(), None, CacheStatus::Miss),
+    };
+    let (mut request, unresolved) = resolve_latest(state, request).await;
+    let mut pinned = unresolved.is_some();
+    // Caches in front of the proxy key on the call as sent, with `latest`,
+    // so they must not be told the pinned entry's lifetime.
+    let ttl_cap = unresolved
+        .as_ref()
+        .map(|original| state.cache.ttl_for(original));
+    let client_ttl = |ttl: Duration| ttl_cap.map_or(ttl, |cap| ttl.min(cap));
 
     let original_id = request.id.clone();
     let cache_key = request.cache_key();
-    let should_cache = cache_policy::should_cache(&request.method);
+    let mut should_cache = state.cache.should_cache(&request.method);
+
+    // A bypassing call neither joins an in-flight call nor takes over the
+    // slot of one others are already waiting on.
+    let mut tx = None;
 
     // Check cache
-    if should_cache {
-        if let Some(cached) = state.cache.get(&cache_key).await {
-            let mut resp = (*cached).clone();
-            resp.id = original_id;
-            return resp;
+    if should_cache && !bypass_cache {
+        if let Some(hit) = state.cache.get_serialized(&cache_key).await {
+            let reply = Reply::Serialized {
+                body: hit.body,
+                content_hash: hit.content_hash,
+                id: original_id,
+            };
+            return (reply, Some(client_ttl(hit.remaining)), CacheStatus::Hit);
         }
 
-        // Check for in-flight request (coalescing)
-        if let Some(mut rx) = state.cache.subscribe_inflight(&cache_key).await
-            && let Ok(resp) = rx.recv().await
-        {
-            let mut resp = (*resp).clone();
-            resp.id = original_id;
-            return resp;
+        // An identical call failed moments ago; don't ask upstream again yet.
+        if let Some(resp) = state.cache.recent_error(&cache_key).await {
+            debug!(method = %request.method, "upstream error in cooldown");
+            let reply = Reply::Shared {
+                response: resp,
+                id: original_id,
+            };
+            return (reply, None, CacheStatus::Cooldown);
+        }
+
+        // Join an identical call already in flight (coalescing), or register
+        // this one for others to join.
+        let (sender, leader) = state.cache.register_inflight(&cache_key).await;
+        if leader {
+            tx = Some(sender);
+        } else {
+            let mut rx = sender.subscribe();
+            // Only the leader's sender may keep the channel open.
+            drop(sender);
+            match within(deadline, rx.recv()).await {
+                Ok(Ok(resp)) => {
+                    let ttl = storable_ttl(state, &request, &resp).await.map(client_ttl);
+                    let reply = Reply::Shared {
+                        response: resp,
+                        id: original_id,
+                    };
+                    return (reply, ttl, CacheStatus::Coalesced);
+                }
+                Ok(Err(_)) => {}
+                Err(_) => return (deadline_exceeded(&request), None, CacheStatus::Miss),
+            }
         }
     }
 
-    // Register in-flight
-    let tx = if should_cache {
-        Some(state.cache.register_inflight(&cache_key).await)
-    } else {
-        None
-    };
+    // Large non-cacheable responses can skip buffering entirely, unless a
+    // strip rule needs to rewrite the result.
+    let stream_threshold = stream_threshold.filter(|_| {
+        !should_cache
+            && !state
+                .options
+                .strip_fields
+                .iter()
+                .any(|rule| rule.method == request.method)
+    });
 
     // Forward to upstream
-    let result = state.upstream.send_request(&request).await;
-
-    match result {
-        Ok(mut response) => {
-            response.id = original_id;
-
-            if should_cache && response.error.is_none() {
-                let ttl = cache_policy::ttl_for_request(&request, state.cache.default_ttl());
-                let cached = Arc::new(response.clone());
-                state
-                    .cache
-                    .insert(cache_key.clone(), cached.clone(), ttl)
-                    .await;
-
-                if let Some(tx) = tx {
-                    let _ = tx.send(cached);
+    let forward = async {
+        if let Some(threshold) = stream_threshold {
+            state
+                .upstream
+                .send_request_streaming(&request, threshold, affinity, forwarded)
+                .await
+        } else {
+            match split_getlogs(state, &request).await {
+                Some(Ok(parts)) => getlogs::send_split(&state.upstream, parts, forwarded).await,
+                Some(Err(e)) => Err(e),
+                None => {
+                    state
+                        .upstream
+                        .send_request_with_affinity(&request, affinity, forwarded)
+                        .await
                 }
-                state.cache.remove_inflight(&cache_key).await;
-            } else if let Some(_tx) = tx {
-                state.cache.remove_inflight(&cache_key).await;
             }
-
-            response
+            .map(UpstreamReply::Parsed)
+        }
+    };
+    let mut result = match within(deadline, forward).await.and_then(|r| r) {
+        Ok(UpstreamReply::Stream(stream)) => {
+            return (Reply::Stream(stream), None, CacheStatus::Miss);
+        }
+        Ok(UpstreamReply::Parsed(response)) => Ok(response),
+        Err(e) => Err(e),
+    };
+    // The resolved block came from a health probe, so the backend that took
+    // the call may not have it yet. Ask again for `latest`, uncached.
+    if let Some(original) = unresolved
+        && result
+            .as_ref()
+            .is_ok_and(|r| r.error.is_some() || r.result.as_ref().is_none_or(|v| v.is_null()))
+    {
+        debug!(method = %request.method, "resolved block not served, retrying with latest");
+        if tx.take().is_some() {
+            state.cache.remove_inflight(&cache_key).await;
+        }
+        should_cache = false;
+        pinned = false;
+        let retry = state
+            .upstream
+            .send_request_with_affinity(&original, affinity, forwarded);
+        result = within(deadline, retry).await.and_then(|r| r);
+        request = original;
+    }
+    match result {
+        Ok(response) => {
+            let (reply, cache_ttl) = store_response(
+                state,
+                &request,
+                cache_key,
+                should_cache,
+                pinned,
+                tx,
+                response,
+            )
+            .await;
+            (reply, cache_ttl.map(client_ttl), CacheStatus::Miss)
         }
         Err(e) => {
-            if let Some(_tx) = tx {
+            if tx.is_some() {
                 state.cache.remove_inflight(&cache_key).await;
             }
-            error!(method = %request.method, error = %e, "all upstreams failed");
-            JsonRpcResponse::internal_error(request.id)
+            let detail = error_detail(state, started, &e);
+            (
+                failure_reply(state, request, &e, detail),
+                None,
+                CacheStatus::Miss,
+            )
+        }
+    }
+}
+
+/// The reply for a call that failed upstream with `e`, with `detail` as
+/// `error.data` where the error carries it.
+fn failure_reply(
+    state: &AppState,
+    request: JsonRpcRequest,
+    e: &RpcProxyError,
+    detail: Option<serde_json::Value>,
+) -> Reply {
+    match e {
+        RpcProxyError::AllUpstreamsFailed { .. } => {
+            error!(method = %request.method, "all upstreams failed");
+            let mut resp = JsonRpcResponse::error(
+                request.id,
+                state.options.upstream_down_code,
+                "All upstream backends failed",
+            );
+            if let Some(detail) = detail {
+                resp = resp.with_error_data(detail);
+            }
+            Reply::UpstreamFailure {
+                response: resp,
+                status: state.options.upstream_down_status,
+                retry_after: None,
+            }
+        }
+        RpcProxyError::RateLimited { retry_after } => {
+            warn!(method = %request.method, "all upstreams rate limited");
+            let mut resp = JsonRpcResponse::error(request.id, -32005, "Upstream rate limited");
+            if let Some(detail) = detail {
+                resp = resp.with_error_data(detail);
+            }
+            Reply::UpstreamFailure {
+                response: resp,
+                status: Some(StatusCode::TOO_MANY_REQUESTS),
+                retry_after: *retry_after,
+            }
+        }
+        RpcProxyError::DeadlineExceeded => deadline_exceeded(&request),
+        e @ RpcProxyError::NoQuorum { .. } => {
+            warn!(method = %request.method, error = %e, "quorum read failed");
+            let mut resp =
+                JsonRpcResponse::error(request.id, -32008, "Backends did not agree on a result");
+            if let Some(detail) = detail {
+                resp = resp.with_error_data(detail);
+            }
+            resp.into()
+        }
+        RpcProxyError::ResponseTooLarge { limit } => {
+            let message = format!(
+                "Response exceeds {limit} bytes; narrow the request, e.g. a smaller eth_getLogs block range"
+            );
+            JsonRpcResponse::error(request.id, -32007, message).into()
+        }
+        RpcProxyError::RangeTooWide { max_blocks } => {
+            warn!(method = %request.method, max_blocks, "eth_getLogs span too wide to split");
+            let message = format!("Block range too wide; query at most {max_blocks} blocks");
+            JsonRpcResponse::error(request.id, -32602, message).into()
+        }
+        e => {
+            error!(method = %request.method, error = %e, "upstream request failed");
+            let resp = match detail {
+                Some(detail) => JsonRpcResponse::internal_error_with_data(request.id, detail),
+                None => JsonRpcResponse::internal_error(request.id),
+            };
+            resp.into()
+        }
+    }
+}
+
+/// Normalizes a call as configured (version, alias, params) and checks that
+/// it may be forwarded. A call that may not gets its error response instead.
+fn prepare_request(
+    state: &AppState,
+    mut request: JsonRpcRequest,
+) -> Result<JsonRpcRequest, Box<JsonRpcResponse>> {
+    // Legacy clients omit the version or send "1.0"; upstreams always get "2.0".
+    if state.options.lenient_jsonrpc && matches!(request.jsonrpc.as_str(), "" | "1.0") {
+        request.jsonrpc = "2.0".to_string();
+    }
+    if !request.is_valid() {
+        return Err(Box::new(JsonRpcResponse::invalid_request(request.id)));
+    }
+
+    // Rewrite aliases first so aliased and canonical calls share a cache entry.
+    if let Some(canonical) = state.options.method_aliases.get(&request.method) {
+        request.method = canonical.clone();
+    }
+
+    // Checked after aliasing so an alias can't smuggle a write through.
+    if state.options.read_only && cache_policy::is_write_method(&request.method) {
+        debug!(method = %request.method, "write method refused in read-only mode");
+        return Err(Box::new(JsonRpcResponse::method_not_allowed(request.id)));
+    }
+
+    // Some upstreams reject `"params": null`, which is what an omitted field becomes.
+    if state.options.normalize_params && request.params.is_null() {
+        request.params = serde_json::Value::Array(Vec::new());
+    }
+    Ok(request)
+}
+
+/// With `--resolve-latest`, pins a `latest` block parameter to the best
+/// recently probed block. Also returns the call as sent if it was changed.
+async fn resolve_latest(
+    state: &AppState,
+    request: JsonRpcRequest,
+) -> (JsonRpcRequest, Option<JsonRpcRequest>) {
+    let Some(window) = state.options.resolve_latest else {
+        return (request, None);
+    };
+    if !state.cache.should_cache(&request.method) {
+        return (request, None);
+    }
+    let Some(block) = state.upstream.latest_block_seen_within(window).await else {
+        return (request, None);
+    };
+    let mut resolved = request.clone();
+    if cache_policy::resolve_latest(&mut resolved, block) {
+        (resolved, Some(request))
+    } else {
+        (request, None)
+    }
+}
+
+/// Turns a successful upstream response into the call's reply: restores the
+/// caller's id, applies strip rules, and caches it if it may be cached,
+/// handing it to coalesced waiters through `tx`. An error that isn't cached
+/// is kept for the error cooldown instead. A `pinned` call's key
+/// moves on with the chain, so its success is kept as long as an immutable
+/// one.
+async fn store_response(
+    state: &AppState,
+    request: &JsonRpcRequest,
+    cache_key: String,
+    should_cache: bool,
+    pinned: bool,
+    tx: Option<broadcast::Sender<Arc<JsonRpcResponse>>>,
+    mut response: JsonRpcResponse,
+) -> (Reply, Option<Duration>) {
+    response.id = request.id.clone();
+    if let Some(result) = response.result.as_mut() {
+        apply_strip_rules(&state.options.strip_fields, &request.method, result);
+    }
+
+    if should_cache && let Some(mut ttl) = storable_ttl(state, request, &response).await {
+        if pinned && response.error.is_none() {
+            ttl = ttl.max(Duration::from_secs(cache_policy::IMMUTABLE_TTL_SECS));
+        }
+        let cached = Arc::new(response);
+        state
+            .cache
+            .insert(cache_key.clone(), cached.clone(), ttl)
+            .await;
+
+        if let Some(tx) = tx {
+            let _ = tx.send(cached.clone());
+            state.cache.remove_inflight(&cache_key).await;
+        }
+
+        let reply = Reply::Shared {
+            response: cached,
+            id: request.id.clone(),
+        };
+        return (reply, Some(ttl));
+    }
+
+    if let Some(_tx) = tx {
+        state.cache.remove_inflight(&cache_key).await;
+    }
+    if should_cache && response.error.is_some() {
+        let response = Arc::new(response);
+        state.cache.note_error(&cache_key, response.clone()).await;
+        let reply = Reply::Shared {
+            id: request.id.clone(),
+            response,
+        };
+        return (reply, None);
+    }
+    (Reply::Upstream(response), None)
+}
+
+/// How long `response` may be cached, judging requested blocks against the
+/// best block the health checks have seen.
+async fn storable_ttl(
+    state: &AppState,
+    request: &JsonRpcRequest,
+    response: &JsonRpcResponse,
+) -> Option<Duration> {
+    let latest_block = state.upstream.latest_block().await;
+    state.cache.storable_ttl(request, response, latest_block)
+}
+
+/// With `--upstream-batching`, sends a batch upstream as one call when none
+/// of its calls is cached or a write, and each would be forwarded unchanged.
+/// Returns `None` when the batch should be handled call by call instead.
+/// Once the batch may have reached a backend, a failure is the answer to
+/// every call rather than a reason to send them again one by one.
+async fn forward_batch(
+    state: &AppState,
+    requests: &[JsonRpcRequest],
+    affinity: Option<&str>,
+    forwarded: &[TargetHeader],
+    deadline: Option<Instant>,
+    bypass_cache: bool,
+) -> Option<Vec<(Reply, Option<Duration>)>> {
+    if !state.options.upstream_batching || requests.len() < 2 {
+        return None;
+    }
+    let mut prepared = Vec::with_capacity(requests.len());
+    for request in requests {
+        let request = prepare_request(state, request.clone()).ok()?;
+        if cache_policy::is_write_method(&request.method) {
+            return None;
         }
+        if state.cache.should_cache(&request.method)
+            && !bypass_cache
+            && state.cache.contains_key(&request.cache_key())
+        {
+            return None;
+        }
+        if split_getlogs(state, &request).await.is_some() {
+            return None;
+        }
+        prepared.push(request);
+    }
+
+    let started = Instant::now();
+    let batch = state.upstream.send_batch(&prepared, affinity, forwarded);
+    let (result, backend) = track_backend(within(deadline, batch)).await;
+    let responses = match result.and_then(|r| r) {
+        Ok(Some(responses)) => responses,
+        Ok(None) => return None,
+        Err(e) => return Some(batch_failure(state, prepared, &e, started, backend)),
+    };
+    let mut replies = Vec::with_capacity(prepared.len());
+    for (request, response) in prepared.iter().zip(responses) {
+        let cache_key = request.cache_key();
+        let should_cache = state.cache.should_cache(&request.method);
+        let (reply, cache_ttl) = store_response(
+            state,
+            request,
+            cache_key,
+            should_cache,
+            false,
+            None,
+            response,
+        )
+        .await;
+        state.recent.record(
+            request.method.clone(),
+            started.elapsed(),
+            CacheStatus::Miss,
+            backend.clone(),
+            reply.error_summary(),
+        );
+        replies.push((reply, cache_ttl));
+    }
+    Some(replies)
+}
+
+/// Answers each call of a batch that failed upstream as one with `e`.
+fn batch_failure(
+    state: &AppState,
+    requests: Vec<JsonRpcRequest>,
+    e: &RpcProxyError,
+    started: Instant,
+    backend: Option<String>,
+) -> Vec<(Reply, Option<Duration>)> {
+    requests
+        .into_iter()
+        .map(|request| {
+            let method = request.method.clone();
+            let detail = error_detail(state, started, e);
+            let reply = failure_reply(state, request, e, detail);
+            state.recent.record(
+                method,
+                started.elapsed(),
+                CacheStatus::Miss,
+                backend.clone(),
+                reply.error_summary(),
+            );
+            (reply, None)
+        })
+        .collect()
+}
+
+/// How often `warm_cache` checks whether a backend has come up.
+const WARM_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Fills the cache with each of `methods`, called with empty params, once a
+/// healthy backend has reported a block. Runs in the background at startup.
+pub async fn warm_cache(state: AppState, methods: Vec<String>) {
+    if !state.cache.is_enabled() {
+        info!("cache disabled, skipping cache warming");
+        return;
+    }
+    while !state.upstream.has_healthy_backend_with_block().await {
+        tokio::time::sleep(WARM_POLL_INTERVAL).await;
+    }
+
+    let mut warmed = 0;
+    for method in &methods {
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            method: method.clone(),
+            params: serde_json::Value::Array(Vec::new()),
+            id: serde_json::Value::from(1),
+        };
+        // Only a response that was stored comes back with a cache TTL.
+        let (_, cache_ttl) =
+            handle_single_request(&state, request, None, None, &[], None, false).await;
+        if cache_ttl.is_some() {
+            warmed += 1;
+        } else {
+            warn!(method = %method, "cache warming call failed");
+        }
+    }
+    info!(warmed, total = methods.len(), "cache warmed");
+}
+
+/// `error.data` for a failed call when `--verbose-errors` is on: the backends
+/// tried and their errors (or the error itself), and the time spent.
+fn error_detail(
+    state: &AppState,
+    started: Instant,
+    error: &RpcProxyError,
+) -> Option<serde_json::Value> {
+    if !state.options.verbose_errors {
+        return None;
+    }
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    Some(match error {
+        RpcProxyError::AllUpstreamsFailed { attempts } => serde_json::json!({
+            "attempts": attempts,
+            "elapsed_ms": elapsed_ms,
+        }),
+        e => serde_json::json!({
+            "error": e.to_string(),
+            "elapsed_ms": elapsed_ms,
+        }),
+    })
+}
+
+/// The reply for a call whose `X-Request-Timeout-Ms` budget ran out.
+fn deadline_exceeded(request: &JsonRpcRequest) -> Reply {
+    warn!(method = %request.method, "request deadline exceeded");
+    JsonRpcResponse::error(request.id.clone(), -32006, "Request deadline exceeded").into()
+}
+
+/// Sub-queries for an `eth_getLogs` call wider than `--split-getlogs`, if any.
+async fn split_getlogs(
+    state: &AppState,
+    request: &JsonRpcRequest,
+) -> Option<Result<Vec<JsonRpcRequest>, RpcProxyError>> {
+    let max_range = state.options.split_getlogs?;
+    if request.method != "eth_getLogs" {
+        return None;
     }
+    let latest = state.upstream.latest_block().await;
+    getlogs::split_request(request, latest, max_range)
 }
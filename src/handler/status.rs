@@ -1,15 +1,36 @@
-use axum::extract::State;
+use std::time::Duration;
+
+use axum::extract::{Path, State};
 use axum::http::{HeaderMap, StatusCode};
-use axum::response::{IntoResponse, Json};
-use tracing::warn;
+use axum::response::{IntoResponse, Json, Response};
+use tracing::{info, warn};
 
 use super::AppState;
-use super::auth::check_bearer_token;
+use super::auth::{check_bearer_token, unauthorized_response};
+
+/// Liveness probe: 200 whenever the server is able to answer requests at all.
+/// Unlike `/health` it does not depend on upstream state, so it is safe to use
+/// as a Kubernetes liveness probe during startup.
+pub async fn live_handler() -> impl IntoResponse {
+    (StatusCode::OK, "ok")
+}
+
+/// Whether enough backends are healthy and the chain they report is still advancing.
+fn is_ready(state: &AppState, healthy: usize, block_age: Option<Duration>) -> bool {
+    let fresh = match state.options.max_block_age {
+        Some(max) => block_age.is_some_and(|age| age <= max),
+        None => true,
+    };
+    healthy >= state.options.min_healthy_backends && fresh
+}
 
 /// Lightweight health check for Docker HEALTHCHECK.
-/// Returns 200 only if at least one backend is healthy AND has returned a real block number.
+/// Returns 200 only if at least `min_healthy_backends` backends are healthy AND
+/// have returned a real block number (that advanced within `max_block_age`, if set).
 pub async fn health_handler(State(state): State<AppState>) -> impl IntoResponse {
-    let ok = state.upstream.has_healthy_backend_with_block().await;
+    let healthy = state.upstream.healthy_backend_count().await;
+    let block_age = state.upstream.freshest_block_age().await;
+    let ok = is_ready(&state, healthy, block_age);
     if ok {
         (StatusCode::OK, "ok")
     } else {
@@ -17,47 +38,48 @@ pub async fn health_handler(State(state): State<AppState>) -> impl IntoResponse
     }
 }
 
+/// Build information for deploy verification. Not protected, like `/health`.
+pub async fn version_handler() -> impl IntoResponse {
+    Json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_hash": env!("GIT_HASH"),
+        "build_time": env!("BUILD_TIME"),
+    }))
+}
+
 /// Readiness probe — same as health but returns JSON detail.
-pub async fn readiness_handler(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-) -> impl IntoResponse {
+pub async fn readiness_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
     if !check_bearer_token(&state, &headers) {
         warn!("unauthorized readiness request (missing or bad token)");
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(serde_json::json!({ "error": "Unauthorized" })),
-        );
+        return unauthorized_response(&state);
     }
 
     let statuses = state.upstream.backend_statuses().await;
-    let ok = statuses
-        .iter()
-        .any(|s| s.state == "Healthy" && s.latest_block.is_some());
+    let healthy = state.upstream.healthy_backend_count().await;
+    let block_age = state.upstream.freshest_block_age().await;
+    let ok = is_ready(&state, healthy, block_age);
 
     let body = serde_json::json!({
         "status": if ok { "ok" } else { "unavailable" },
+        "healthy_backends": healthy,
+        "min_healthy_backends": state.options.min_healthy_backends,
+        "block_age_secs": block_age.map(|a| a.as_secs()),
         "backends": statuses,
     });
 
-    if ok {
-        (StatusCode::OK, Json(body))
+    let status = if ok {
+        StatusCode::OK
     } else {
-        (StatusCode::SERVICE_UNAVAILABLE, Json(body))
-    }
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(body)).into_response()
 }
 
 /// Detailed status endpoint showing all targets, their states, and usage statistics.
-pub async fn status_handler(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-) -> impl IntoResponse {
+pub async fn status_handler(State(state): State<AppState>, headers: HeaderMap) -> Response {
     if !check_bearer_token(&state, &headers) {
         warn!("unauthorized status request (missing or bad token)");
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(serde_json::json!({ "error": "Unauthorized" })),
-        );
+        return unauthorized_response(&state);
     }
 
     let statuses = state.upstream.backend_statuses().await;
@@ -69,9 +91,74 @@ pub async fn status_handler(
     let body = serde_json::json!({
         "healthy_backends": healthy_count,
         "total_backends": total,
+        "cache_enabled": state.cache.is_enabled(),
         "cache_entries": cache_entries,
+        "active_requests": state.active.count(),
+        "mirror": state.upstream.mirror_info(),
         "backends": statuses,
     });
 
-    (StatusCode::OK, Json(body))
+    Json(body).into_response()
+}
+
+/// A single backend's entry from `/status`, by its position in `--targets`.
+pub async fn backend_status_handler(
+    State(state): State<AppState>,
+    Path(index): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if !check_bearer_token(&state, &headers) {
+        warn!("unauthorized status request (missing or bad token)");
+        return unauthorized_response(&state);
+    }
+
+    let statuses = state.upstream.backend_statuses().await;
+    // Parsed here rather than by the extractor so a bad token is rejected
+    // before a malformed index.
+    match index.parse::<usize>().ok().and_then(|i| statuses.get(i)) {
+        Some(status) => Json(status).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "error": format!("no backend at index {index}"),
+                "total_backends": statuses.len(),
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// Logs cache and backend totals every `interval`, as a heartbeat for
+/// deployments that don't scrape `/status`. Counts are since startup.
+pub async fn log_stats(state: AppState, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let statuses = state.upstream.backend_statuses().await;
+        let entries = state.cache.entry_count().await;
+        let (hits, misses) = state.cache.hit_counts();
+        let lookups = hits + misses;
+        let hit_rate = if lookups == 0 {
+            0.0
+        } else {
+            hits as f64 / lookups as f64 * 100.0
+        };
+        let backends = statuses
+            .iter()
+            .map(|s| format!("{}={}", s.url, s.state))
+            .collect::<Vec<_>>()
+            .join(", ");
+        info!(
+            cache_entries = entries,
+            active_requests = state.active.count(),
+            cache_hits = hits,
+            cache_misses = misses,
+            hit_rate = %format!("{hit_rate:.1}%"),
+            total_requests = statuses.iter().map(|s| s.total_requests).sum::<u64>(),
+            total_errors = statuses.iter().map(|s| s.total_errors).sum::<u64>(),
+            backends = %backends,
+            "stats"
+        );
+    }
 }
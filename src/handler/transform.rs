@@ -0,0 +1,37 @@
+/// Removes a field from the result of a given method before it is cached or
+/// returned, configured with `--strip-field method:path.to.field`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StripRule {
+    pub method: String,
+    pub path: Vec<String>,
+}
+
+/// Applies every rule matching `method` to `result`.
+pub fn apply_strip_rules(rules: &[StripRule], method: &str, result: &mut serde_json::Value) {
+    for rule in rules.iter().filter(|r| r.method == method) {
+        strip_path(result, &rule.path);
+    }
+}
+
+/// Removes the object field at `path`. Arrays along the way are descended
+/// element-wise, so `transactions.input` strips `input` from every transaction.
+pub fn strip_path(value: &mut serde_json::Value, path: &[String]) {
+    let Some((first, rest)) = path.split_first() else {
+        return;
+    };
+    match value {
+        serde_json::Value::Object(map) => {
+            if rest.is_empty() {
+                map.remove(first);
+            } else if let Some(child) = map.get_mut(first) {
+                strip_path(child, rest);
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                strip_path(item, path);
+            }
+        }
+        _ => {}
+    }
+}
@@ -1,5 +1,5 @@
 use std::io::Write;
-use std::net::TcpStream;
+use std::net::{TcpStream, ToSocketAddrs};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -7,7 +7,7 @@ use tokio::time;
 use tracing::info;
 
 use crate::error::RpcProxyError;
-use crate::upstream::UpstreamManager;
+use crate::upstream::{Target, UpstreamManager, sanitize_url};
 
 pub async fn start_health_checker(upstream: Arc<UpstreamManager>, interval_secs: u64) {
     let interval = Duration::from_secs(interval_secs);
@@ -15,7 +15,18 @@ pub async fn start_health_checker(upstream: Arc<UpstreamManager>, interval_secs:
 
     info!(interval_secs = %interval_secs, "starting health checker");
 
-    upstream.check_all_backends(probe_backend_url).await;
+    let proxy = upstream.upstream_proxy();
+    let probe = |target: Target| async move { probe_hex(&target, "eth_blockNumber", proxy).await };
+    let syncing = |target: Target| async move { fetch_syncing(&target, proxy).await };
+    // Syncing goes first so a syncing backend's block probe can't briefly
+    // restore it to Healthy.
+    let check = || async {
+        if upstream.checks_syncing() {
+            upstream.check_syncing(syncing).await;
+        }
+        upstream.check_all_backends(probe).await;
+    };
+    check().await;
 
     let mut ticker = time::interval(interval);
     ticker.tick().await;
@@ -28,69 +39,244 @@ pub async fn start_health_checker(upstream: Arc<UpstreamManager>, interval_secs:
                 ticker.reset();
             },
         }
-        upstream.check_all_backends(probe_backend_url).await;
+        check().await;
+    }
+}
+
+pub async fn probe_backend(target: Target) -> Result<u64, RpcProxyError> {
+    probe_hex(&target, "eth_blockNumber", None).await
+}
+
+/// Fetches a backend's chain id with `eth_chainId`.
+pub async fn probe_chain_id(target: Target) -> Result<u64, RpcProxyError> {
+    probe_hex(&target, "eth_chainId", None).await
+}
+
+/// Asks a backend with `eth_syncing` whether it is still catching up.
+pub async fn probe_syncing(target: Target) -> Result<bool, RpcProxyError> {
+    fetch_syncing(&target, None).await
+}
+
+/// `eth_syncing` answers `false` when synced and a progress object otherwise.
+async fn fetch_syncing(
+    target: &Target,
+    proxy: Option<&reqwest::Proxy>,
+) -> Result<bool, RpcProxyError> {
+    match probe_result(target, "eth_syncing", proxy).await? {
+        serde_json::Value::Bool(syncing) => Ok(syncing),
+        serde_json::Value::Object(_) => Ok(true),
+        other => Err(RpcProxyError::HealthProbe(format!(
+            "invalid eth_syncing result: {other}"
+        ))),
     }
 }
 
-pub async fn probe_backend_url(url: String) -> Result<u64, RpcProxyError> {
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(5))
+/// Calls a parameterless method whose result is a hex quantity, through
+/// `proxy` if one is configured.
+async fn probe_hex(
+    target: &Target,
+    method: &str,
+    proxy: Option<&reqwest::Proxy>,
+) -> Result<u64, RpcProxyError> {
+    let result = probe_result(target, method, proxy).await?;
+    let result = result
+        .as_str()
+        .ok_or_else(|| RpcProxyError::HealthProbe("missing result field".into()))?;
+
+    u64::from_str_radix(result.trim_start_matches("0x"), 16)
+        .map_err(|e| RpcProxyError::HealthProbe(format!("invalid {method} result: {e}")))
+}
+
+/// Calls a parameterless method and returns its `result`.
+async fn probe_result(
+    target: &Target,
+    method: &str,
+    proxy: Option<&reqwest::Proxy>,
+) -> Result<serde_json::Value, RpcProxyError> {
+    let mut client = reqwest::Client::builder().timeout(Duration::from_secs(5));
+    if let Some(proxy) = proxy {
+        client = client.proxy(proxy.clone());
+    }
+    let client = client
         .build()
         .map_err(|e| RpcProxyError::HealthProbe(format!("client build: {e}")))?;
 
     let body = serde_json::json!({
         "jsonrpc": "2.0",
-        "method": "eth_blockNumber",
+        "method": method,
         "params": [],
         "id": 1
     });
 
-    let resp = client
-        .post(&url)
-        .header("content-type", "application/json")
+    let mut builder = client
+        .post(&target.url)
+        .header("content-type", "application/json");
+    for h in &target.headers {
+        builder = builder.header(&h.name, &h.value);
+    }
+    if let Some(auth) = &target.basic_auth {
+        builder = auth.apply(builder);
+    }
+
+    let resp = builder
         .json(&body)
         .send()
         .await
-        .map_err(|e| RpcProxyError::UpstreamRequest(e.to_string()))?;
+        .map_err(|e| RpcProxyError::UpstreamRequest(e.without_url().to_string()))?;
 
     if !resp.status().is_success() {
         return Err(RpcProxyError::UpstreamHttp(resp.status().as_u16()));
     }
 
-    let json: serde_json::Value = resp
+    let mut json: serde_json::Value = resp
         .json()
         .await
-        .map_err(|e| RpcProxyError::BodyRead(e.to_string()))?;
+        .map_err(|e| RpcProxyError::BodyRead(e.without_url().to_string()))?;
 
-    let result = json
-        .get("result")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| RpcProxyError::HealthProbe("missing result field".into()))?;
+    json.get_mut("result")
+        .map(serde_json::Value::take)
+        .ok_or_else(|| RpcProxyError::HealthProbe("missing result field".into()))
+}
+
+/// Outcome of probing one target for `--check-targets`.
+#[derive(Debug)]
+pub struct TargetCheck {
+    /// The target URL as it should be printed.
+    pub url: String,
+    pub block: Result<u64, RpcProxyError>,
+    pub chain_id: Result<u64, RpcProxyError>,
+}
 
-    let block = u64::from_str_radix(result.trim_start_matches("0x"), 16)
-        .map_err(|e| RpcProxyError::HealthProbe(format!("invalid block number: {e}")))?;
+impl TargetCheck {
+    pub fn is_ok(&self) -> bool {
+        self.block.is_ok() && self.chain_id.is_ok()
+    }
+}
 
-    Ok(block)
+/// Probes every target once for its latest block and chain id.
+pub async fn check_targets(
+    targets: &[Target],
+    show_full_urls: bool,
+    proxy: Option<&reqwest::Proxy>,
+) -> Vec<TargetCheck> {
+    let mut checks = Vec::with_capacity(targets.len());
+    for target in targets {
+        let (block, chain_id) = tokio::join!(
+            probe_hex(target, "eth_blockNumber", proxy),
+            probe_hex(target, "eth_chainId", proxy)
+        );
+        let url = if show_full_urls {
+            target.url.clone()
+        } else {
+            sanitize_url(&target.url)
+        };
+        checks.push(TargetCheck {
+            url,
+            block,
+            chain_id,
+        });
+    }
+    checks
 }
 
+/// Renders `check_targets` results as a table, one row per target.
+pub fn format_target_report(checks: &[TargetCheck]) -> String {
+    let width = checks
+        .iter()
+        .map(|c| c.url.len())
+        .chain(std::iter::once("TARGET".len()))
+        .max()
+        .unwrap_or(0);
+
+    let mut out = format!(
+        "{:<width$}  {:<6}  {:>12}  {:>10}\n",
+        "TARGET", "STATUS", "BLOCK", "CHAIN ID"
+    );
+    for check in checks {
+        let status = if check.is_ok() { "ok" } else { "FAIL" };
+        let block = check.block.as_ref().map_or("-".into(), u64::to_string);
+        let chain_id = check.chain_id.as_ref().map_or("-".into(), u64::to_string);
+        out.push_str(&format!(
+            "{:<width$}  {status:<6}  {block:>12}  {chain_id:>10}",
+            check.url
+        ));
+        if let Some(e) = check.block.as_ref().err().or(check.chain_id.as_ref().err()) {
+            out.push_str(&format!("  {e}"));
+        }
+        out.push('\n');
+    }
+    out
+}
 
-/// Perform an HTTP health check against the running instance using only std.
+/// Probes every configured target once and prints a report.
+/// Returns 0 if all targets answered, 1 otherwise.
+/// Used by `rpcproxy --check-targets` as a pre-deploy smoke test.
+pub async fn run_target_check(
+    targets: &[Target],
+    show_full_urls: bool,
+    proxy: Option<&reqwest::Proxy>,
+) -> i32 {
+    let checks = check_targets(targets, show_full_urls, proxy).await;
+    print!("{}", format_target_report(&checks));
+    if checks.iter().all(TargetCheck::is_ok) {
+        0
+    } else {
+        1
+    }
+}
+
+/// Probes every target once, as `--check-targets` does, and fails unless at
+/// least one returned its latest block. The error lists each target's failure.
+/// Used by `--require-backend-on-start` before the server binds.
+pub async fn require_reachable_target(
+    targets: &[Target],
+    show_full_urls: bool,
+    proxy: Option<&reqwest::Proxy>,
+) -> Result<(), String> {
+    let checks = check_targets(targets, show_full_urls, proxy).await;
+    if checks.iter().any(|c| c.block.is_ok()) {
+        return Ok(());
+    }
+    let failures: Vec<String> = checks
+        .iter()
+        .filter_map(|c| Some(format!("{}: {}", c.url, c.block.as_ref().err()?)))
+        .collect();
+    Err(format!("no backend reachable ({})", failures.join("; ")))
+}
+
+
+/// Perform an HTTP health check against a running instance using only std.
+/// Requests `path` on `host:port`, sending `token` as a Bearer header if given
+/// (needed for `/readiness` when the proxy has a token).
 /// Returns 0 if the server responds with HTTP 200, 1 otherwise.
-/// Used by `rpcproxy --health` for Docker HEALTHCHECK without curl.
-pub fn run_health_check(port: u16) -> i32 {
-    let addr = format!("127.0.0.1:{port}");
+/// Used by `rpcproxy --health` for Docker HEALTHCHECK without curl, and for
+/// monitoring a remote instance.
+pub fn run_health_check(host: &str, port: u16, path: &str, token: Option<&str>) -> i32 {
     let timeout = Duration::from_secs(5);
 
-    let mut stream = match TcpStream::connect_timeout(&addr.parse().unwrap(), timeout) {
-        Ok(s) => s,
-        Err(_) => return 1,
+    let Ok(addrs) = (host, port).to_socket_addrs() else {
+        return 1;
+    };
+    let Some(mut stream) = addrs
+        .into_iter()
+        .find_map(|addr| TcpStream::connect_timeout(&addr, timeout).ok())
+    else {
+        return 1;
     };
 
     let _ = stream.set_read_timeout(Some(timeout));
     let _ = stream.set_write_timeout(Some(timeout));
 
+    let host_header = if host.contains(':') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    };
+    let auth = token
+        .map(|t| format!("Authorization: Bearer {t}\r\n"))
+        .unwrap_or_default();
     let request = format!(
-        "GET /health HTTP/1.1\r\nHost: localhost:{port}\r\nConnection: close\r\n\r\n"
+        "GET {path} HTTP/1.1\r\nHost: {host_header}\r\n{auth}Connection: close\r\n\r\n"
     );
     if stream.write_all(request.as_bytes()).is_err() {
         return 1;
@@ -2,4 +2,4 @@ mod request;
 mod response;
 
 pub use request::{JsonRpcBody, JsonRpcRequest};
-pub use response::JsonRpcResponse;
+pub use response::{JsonRpcResponse, ResponseWithId, SerializedResponse};
@@ -2,6 +2,9 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JsonRpcRequest {
+    /// Empty when the client omitted it; `is_valid` then rejects the call
+    /// unless lenient mode rewrites it first.
+    #[serde(default)]
     pub jsonrpc: String,
     pub method: String,
     #[serde(default)]
@@ -11,7 +14,11 @@ pub struct JsonRpcRequest {
 
 impl JsonRpcRequest {
     pub fn cache_key(&self) -> String {
-        let mut params = self.params.clone();
+        // Omitted, `null`, and `[]` params all mean "no arguments".
+        let mut params = match &self.params {
+            serde_json::Value::Null => serde_json::Value::Array(Vec::new()),
+            params => params.clone(),
+        };
         normalize_value(&mut params);
         format!(
             "{}:{}",
@@ -25,6 +32,15 @@ impl JsonRpcRequest {
     }
 }
 
+/// Largest magnitude below which every integer is exactly representable as
+/// an `f64`.
+const MAX_EXACT_FLOAT_INT: f64 = 9_007_199_254_740_992.0;
+
+/// Brings equivalent params to one form for the cache key. Object keys
+/// already serialize sorted, as serde_json's map is ordered by key, so
+/// `{"toBlock":..,"fromBlock":..}` and its reordering share an entry; array
+/// order is positional and kept. Whole floats such as `4.0` or
+/// `4e0` become the integer `4`.
 fn normalize_value(value: &mut serde_json::Value) {
     match value {
         serde_json::Value::Object(map) => {
@@ -37,6 +53,14 @@ fn normalize_value(value: &mut serde_json::Value) {
                 normalize_value(v);
             }
         }
+        serde_json::Value::Number(n) => {
+            if let Some(f) = n.as_f64().filter(|_| n.is_f64())
+                && f.fract() == 0.0
+                && f.abs() < MAX_EXACT_FLOAT_INT
+            {
+                *value = serde_json::Value::from(f as i64);
+            }
+        }
         _ => {}
     }
 }
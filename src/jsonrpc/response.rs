@@ -1,3 +1,4 @@
+use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,7 +19,30 @@ pub struct JsonRpcError {
     pub data: Option<serde_json::Value>,
 }
 
+/// A borrowed view of a response with a different `id`, serialized exactly
+/// like the owned response would be. Lets a shared cached response be written
+/// out for another caller without deep-cloning its result.
+#[derive(Debug, Serialize)]
+pub struct ResponseWithId<'a> {
+    jsonrpc: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<&'a serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a JsonRpcError>,
+    id: &'a serde_json::Value,
+}
+
 impl JsonRpcResponse {
+    /// Borrows this response for serialization under the given `id`.
+    pub fn with_id<'a>(&'a self, id: &'a serde_json::Value) -> ResponseWithId<'a> {
+        ResponseWithId {
+            jsonrpc: &self.jsonrpc,
+            result: self.result.as_ref(),
+            error: self.error.as_ref(),
+            id,
+        }
+    }
+
     pub fn error(id: serde_json::Value, code: i64, message: impl Into<String>) -> Self {
         Self {
             jsonrpc: "2.0".to_string(),
@@ -40,7 +64,74 @@ impl JsonRpcResponse {
         Self::error(id, -32600, "Invalid request")
     }
 
+    pub fn method_not_allowed(id: serde_json::Value) -> Self {
+        Self::error(id, -32601, "Method not allowed")
+    }
+
     pub fn internal_error(id: serde_json::Value) -> Self {
         Self::error(id, -32603, "Internal error")
     }
+
+    /// Like `internal_error`, with diagnostic detail in `error.data`.
+    pub fn internal_error_with_data(id: serde_json::Value, data: serde_json::Value) -> Self {
+        Self::error(id, -32603, "Internal error").with_error_data(data)
+    }
+
+    /// Attaches `data` to this response's error, if it is one.
+    pub fn with_error_data(mut self, data: serde_json::Value) -> Self {
+        if let Some(error) = self.error.as_mut() {
+            error.data = Some(data);
+        }
+        self
+    }
+}
+
+/// A response serialized once with its `id` value left off, so it can be
+/// written for any caller by appending their id. Relies on `id` being the
+/// last field `JsonRpcResponse` serializes.
+#[derive(Debug, Clone)]
+pub struct SerializedResponse {
+    /// Everything up to and including `"id":`.
+    prefix: Bytes,
+    is_error: bool,
+}
+
+impl SerializedResponse {
+    pub fn new(response: &JsonRpcResponse) -> Self {
+        const OPEN_ID: &[u8] = b"null}";
+        let mut bytes = serde_json::to_vec(&response.with_id(&serde_json::Value::Null))
+            .expect("JSON-RPC responses always serialize");
+        debug_assert!(bytes.ends_with(b"\"id\":null}"));
+        bytes.truncate(bytes.len() - OPEN_ID.len());
+        Self {
+            prefix: Bytes::from(bytes),
+            is_error: response.error.is_some(),
+        }
+    }
+
+    /// Whether the response carries a JSON-RPC error.
+    pub fn is_error(&self) -> bool {
+        self.is_error
+    }
+
+    /// Appends the complete response, carrying `id`, to `out`.
+    pub fn write_with_id(&self, id: &serde_json::Value, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.prefix);
+        serde_json::to_writer(&mut *out, id).expect("JSON values always serialize");
+        out.push(b'}');
+    }
+
+    /// Size of the serialized response, excluding the id.
+    pub fn size(&self) -> usize {
+        self.prefix.len() + 1
+    }
+
+    /// A hash of the response without its id: equal for any two responses
+    /// with the same content.
+    pub fn content_hash(&self) -> u64 {
+        let digest = ring::digest::digest(&ring::digest::SHA256, &self.prefix);
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&digest.as_ref()[..8]);
+        u64::from_be_bytes(bytes)
+    }
 }
@@ -4,4 +4,6 @@ pub mod error;
 pub mod handler;
 pub mod health;
 pub mod jsonrpc;
+pub mod server;
+pub mod telemetry;
 pub mod upstream;
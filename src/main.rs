@@ -1,24 +1,45 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 
 use axum::Router;
+use axum::http::StatusCode;
 use axum::routing::{get, post};
 use clap::Parser;
 use tracing::info;
+use tracing_subscriber::Layer;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
-use rpcproxy::cache::RpcCache;
-use rpcproxy::config::{Config, validate_token};
+use rpcproxy::cache::{RpcCache, policy as cache_policy};
+use rpcproxy::config::{
+    Config, LogFormat, parse_method_alias, parse_method_timeout, parse_route, parse_route_tag,
+    parse_strip_field, parse_target, read_targets_file, validate_targets, validate_token,
+};
 use rpcproxy::handler;
-use rpcproxy::handler::AppState;
+use rpcproxy::handler::active::ActiveRequests;
+use rpcproxy::handler::recent::RecentCalls;
+use rpcproxy::handler::{AppState, HandlerOptions};
 use rpcproxy::health;
-use rpcproxy::upstream::UpstreamManager;
+use rpcproxy::server::{self, ServerOptions};
+#[cfg(feature = "otel")]
+use rpcproxy::telemetry;
+use rpcproxy::upstream::{UpstreamManager, UpstreamOptions, sanitize_url};
 
 #[tokio::main]
 async fn main() {
     let config = Config::parse();
 
     if config.health {
-        std::process::exit(health::run_health_check(config.port));
+        let token = config.token.as_deref().filter(|t| !t.is_empty());
+        std::process::exit(health::run_health_check(
+            &config.health_host,
+            config.port,
+            config.health_endpoint.path(),
+            token,
+        ));
     }
 
     if let Some(ref token) = config.token
@@ -29,23 +50,136 @@ async fn main() {
         std::process::exit(1);
     }
 
+    let mut targets: Vec<_> = parse_or_exit(&config.targets, parse_target, "target");
+    if let Err(e) = validate_targets(&targets) {
+        eprintln!("error: invalid --targets: {e}");
+        std::process::exit(1);
+    }
+    if let Some(path) = &config.targets_file {
+        match read_targets_file(path) {
+            Ok(listed) => targets.extend(listed),
+            Err(e) => {
+                eprintln!("error: invalid --targets-file: {e}");
+                std::process::exit(1);
+            }
+        }
+        if targets.is_empty() {
+            eprintln!("error: no targets in --targets or --targets-file");
+            std::process::exit(1);
+        }
+    }
+
+    if config.check_targets {
+        std::process::exit(
+            health::run_target_check(
+                &targets,
+                config.show_full_urls,
+                config.upstream_proxy.as_ref(),
+            )
+            .await,
+        );
+    }
+    if config.require_backend_on_start
+        && let Err(e) = health::require_reachable_target(
+            &targets,
+            config.show_full_urls,
+            config.upstream_proxy.as_ref(),
+        )
+        .await
+    {
+        eprintln!("error: {e}");
+        std::process::exit(1);
+    }
+
+    let strip_fields = parse_or_exit(&config.strip_fields, parse_strip_field, "--strip-field");
+    let mirror_target = config.mirror_target.as_deref().map(|t| {
+        parse_target(t)
+            .and_then(|target| validate_targets(std::slice::from_ref(&target)).map(|_| target))
+            .unwrap_or_else(|e| {
+                eprintln!("error: invalid --mirror-target: {e}");
+                std::process::exit(1);
+            })
+    });
+    if let Some(method) = config
+        .warm_methods
+        .iter()
+        .find(|m| !cache_policy::should_cache(m))
+    {
+        eprintln!("error: invalid --warm-method: {method} is never cached");
+        std::process::exit(1);
+    }
+    let cors = (!config.cors_origins.is_empty()).then(|| {
+        server::cors_layer(&config.cors_origins).unwrap_or_else(|e| {
+            eprintln!("error: invalid --cors-origin: {e}");
+            std::process::exit(1);
+        })
+    });
+    let method_aliases: HashMap<_, _> =
+        parse_or_exit(&config.method_aliases, parse_method_alias, "--method-alias");
+    let method_timeouts: HashMap<_, _> = parse_or_exit(
+        &config.method_timeouts,
+        parse_method_timeout,
+        "--method-timeout",
+    );
+
+    let mut routes: HashMap<String, Vec<usize>> = HashMap::new();
+    for (method, index) in parse_or_exit::<_, Vec<_>>(&config.routes, parse_route, "--route") {
+        if index >= targets.len() {
+            eprintln!(
+                "error: invalid --route: backend index {index} for {method} is out of range ({} targets)",
+                targets.len()
+            );
+            std::process::exit(1);
+        }
+        routes.entry(method).or_default().push(index);
+    }
+
+    let mut route_tags: HashMap<String, Vec<String>> = HashMap::new();
+    for (method, tag) in
+        parse_or_exit::<_, Vec<_>>(&config.route_tags, parse_route_tag, "--route-tag")
+    {
+        if !targets.iter().any(|t| t.tags.contains(&tag)) {
+            eprintln!("error: invalid --route-tag: no target is tagged '{tag}' for {method}");
+            std::process::exit(1);
+        }
+        route_tags.entry(method).or_default().push(tag);
+    }
+
     let log_level = if config.verbose {
         "debug,hyper=info,reqwest=info"
     } else {
         "warn,rpcproxy=info"
     };
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(log_level)),
-        )
-        .init();
+    let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(log_level));
+    let fmt_layer = match config.log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().boxed(),
+    };
+    let subscriber = tracing_subscriber::registry()
+        .with(env_filter)
+        .with(fmt_layer);
+
+    #[cfg(feature = "otel")]
+    let tracer_provider = config.otlp_endpoint.as_deref().map(|endpoint| {
+        telemetry::init_otlp(endpoint).unwrap_or_else(|e| {
+            eprintln!("error: invalid --otlp-endpoint: {e}");
+            std::process::exit(1);
+        })
+    });
+    #[cfg(feature = "otel")]
+    let subscriber = subscriber.with(tracer_provider.as_ref().map(telemetry::layer));
+
+    subscriber.init();
 
     let token = config.token.clone().filter(|t| !t.is_empty());
 
     info!(
         port = %config.port,
-        targets = ?config.targets,
+        targets = ?targets
+            .iter()
+            .map(|t| if config.show_full_urls { t.url.clone() } else { sanitize_url(&t.url) })
+            .collect::<Vec<_>>(),
         cache_ttl = %config.cache_ttl,
         health_interval = %config.health_interval,
         auth = token.is_some(),
@@ -56,18 +190,86 @@ async fn main() {
     if let Some(ref t) = token {
         info!(path = %format!("/{t}"), "token auth enabled via URL path");
     }
+    if config.no_cache || config.cache_ttl == 0 {
+        info!("response caching disabled");
+    }
 
-    let upstream = Arc::new(UpstreamManager::new(
-        config.targets.clone(),
-        Duration::from_secs(config.request_timeout),
+    let upstream = Arc::new(UpstreamManager::with_options(
+        targets,
+        UpstreamOptions {
+            request_timeout: Duration::from_secs(config.request_timeout),
+            connect_timeout: config.connect_timeout.map(Duration::from_secs),
+            method_timeouts,
+            quorum_methods: config.quorum_methods.iter().cloned().collect(),
+            pool_max_idle_per_host: config.pool_max_idle_per_host,
+            pool_idle_timeout: Duration::from_secs(config.pool_idle_timeout),
+            http2: config.upstream_http2,
+            upstream_proxy: config.upstream_proxy.clone(),
+            show_full_urls: config.show_full_urls,
+            mirror_target,
+            mirror_rate: config.mirror_rate,
+            rate_limit_cooldown: Duration::from_secs(config.rate_limit_cooldown_secs),
+            routes,
+            route_tags,
+            last_resort: !config.no_last_resort,
+            retry_jitter: Duration::from_millis(config.retry_jitter_ms),
+            total_timeout: config.total_request_timeout.map(Duration::from_secs),
+            slow_query_threshold: config.slow_query_threshold_ms.map(Duration::from_millis),
+            state_change_webhook: config.state_change_webhook.clone(),
+            state_change_debounce: Duration::from_secs(config.state_change_debounce_secs),
+            startup_grace: Duration::from_secs(config.startup_grace),
+            failback_delay: Duration::from_secs(config.failback_delay),
+            block_freshness: config.block_freshness_secs.map(Duration::from_secs),
+            max_response_bytes: config.max_response_bytes,
+            check_syncing: config.check_syncing,
+        },
     ));
 
-    let cache = RpcCache::new(config.cache_max_size, config.cache_ttl);
+    let cache = RpcCache::new(config.cache_max_size, config.cache_ttl)
+        .with_enabled(!config.no_cache)
+        .with_hashed_keys(config.hash_cache_keys)
+        .with_namespace(config.cache_namespace.clone())
+        .with_gas_ttl(config.gas_ttl_ms.map(Duration::from_millis))
+        .with_method_not_found_ttl(config.method_not_found_ttl_ms.map(Duration::from_millis))
+        .with_error_cooldown(config.error_cooldown_ms.map(Duration::from_millis))
+        .with_future_blocks_cached(!config.no_cache_future_blocks);
 
+    let active = ActiveRequests::default();
     let state = AppState {
         upstream: upstream.clone(),
         cache,
         token,
+        options: HandlerOptions {
+            cache_control_headers: config.cache_control_headers,
+            min_healthy_backends: config.min_healthy_backends,
+            max_block_age: config.max_block_age_secs.map(Duration::from_secs),
+            strip_fields,
+            method_aliases,
+            split_getlogs: config.split_getlogs,
+            resolve_latest: config.resolve_latest.map(Duration::from_secs),
+            stream_threshold_bytes: config.stream_threshold_bytes,
+            upstream_down_code: config.upstream_down_code,
+            upstream_down_status: config
+                .upstream_down_status
+                .map(|code| StatusCode::from_u16(code).expect("validated by clap")),
+            error_status: config
+                .error_http_status
+                .map(|code| StatusCode::from_u16(code).expect("validated by clap")),
+            sticky_header: config.sticky_header.clone(),
+            verbose_errors: config.verbose_errors,
+            lenient_jsonrpc: config.lenient_jsonrpc,
+            normalize_params: config.normalize_params,
+            read_only: config.read_only,
+            propagate_trace_headers: !config.no_trace_propagation,
+            unauthorized_status: StatusCode::from_u16(config.unauthorized_status)
+                .expect("validated by clap"),
+            allow_query_token: config.allow_query_token,
+            etag: config.etag,
+            upstream_batching: config.upstream_batching,
+            batch_consistency: config.batch_consistency,
+        },
+        recent: RecentCalls::new(config.recent_requests),
+        active: active.clone(),
     };
 
     // Spawn health checker
@@ -76,19 +278,110 @@ async fn main() {
         config.health_interval,
     ));
 
-    let app = Router::new()
+    if config.stats_interval > 0 {
+        tokio::spawn(handler::status::log_stats(
+            state.clone(),
+            Duration::from_secs(config.stats_interval),
+        ));
+    }
+
+    if !config.warm_methods.is_empty() {
+        tokio::spawn(handler::rpc::warm_cache(
+            state.clone(),
+            config.warm_methods.clone(),
+        ));
+    }
+
+    let mut app = Router::new()
+        .route("/live", get(handler::status::live_handler))
         .route("/health", get(handler::status::health_handler))
+        .route("/version", get(handler::status::version_handler))
         .route("/readiness", get(handler::status::readiness_handler))
         .route("/status", get(handler::status::status_handler))
+        .route(
+            "/status/{index}",
+            get(handler::status::backend_status_handler),
+        )
+        .route("/admin/cache/keys", get(handler::admin::cache_keys_handler))
+        .route("/admin/recent", get(handler::admin::recent_handler))
+        .route("/admin/targets", get(handler::admin::targets_handler))
         .route("/{token}", post(handler::rpc::token_rpc_handler))
         .fallback(post(handler::rpc::open_rpc_handler))
         .with_state(state);
+    if let Some(cors) = cors {
+        app = app.layer(cors);
+    }
 
-    let addr = format!("0.0.0.0:{}", config.port);
-    let listener = tokio::net::TcpListener::bind(&addr)
-        .await
-        .expect("failed to bind");
+    let server_options = ServerOptions {
+        http2: config.http2,
+        keepalive_timeout: config.keepalive_timeout.map(Duration::from_secs),
+        active_requests: active,
+    };
+
+    if let Some(path) = &config.unix_socket {
+        serve_unix(path, app, server_options).await;
+    } else {
+        let addr = SocketAddr::new(config.bind, config.port);
+        let listener = match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("error: failed to bind {addr}: {e}");
+                std::process::exit(1);
+            }
+        };
+
+        info!(addr = %addr, http2 = config.http2, "rpcproxy listening");
+        server::serve(listener, app, server_options, server::shutdown_signal()).await;
+    }
+
+    // Flush spans still queued for export.
+    #[cfg(feature = "otel")]
+    if let Some(provider) = tracer_provider
+        && let Err(e) = provider.shutdown()
+    {
+        eprintln!("error: failed to flush traces: {e}");
+    }
+}
+
+/// Parses every value of a repeatable flag, exiting with a clear error on the first bad one.
+fn parse_or_exit<T, C>(values: &[String], parse: fn(&str) -> Result<T, String>, what: &str) -> C
+where
+    C: FromIterator<T>,
+{
+    match values.iter().map(|v| parse(v)).collect::<Result<C, _>>() {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("error: invalid {what}: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn serve_unix(path: &Path, app: Router, options: ServerOptions) {
+    use std::os::unix::fs::FileTypeExt;
+
+    // Remove a socket left behind by a previous run, but never a regular file.
+    if let Ok(meta) = std::fs::symlink_metadata(path)
+        && meta.file_type().is_socket()
+    {
+        let _ = std::fs::remove_file(path);
+    }
+
+    let listener = match tokio::net::UnixListener::bind(path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("error: failed to bind unix socket {}: {e}", path.display());
+            std::process::exit(1);
+        }
+    };
+
+    info!(path = %path.display(), "rpcproxy listening on unix socket");
+    server::serve(listener, app, options, server::shutdown_signal()).await;
+}
 
-    info!(addr = %addr, "rpcproxy listening");
-    axum::serve(listener, app).await.expect("server error");
+#[cfg(not(unix))]
+async fn serve_unix(_path: &Path, _app: Router, _options: ServerOptions) {
+    eprintln!("error: --unix-socket is only supported on unix platforms");
+    std::process::exit(1);
 }
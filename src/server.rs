@@ -0,0 +1,147 @@
+use std::future::Future;
+use std::time::Duration;
+
+use axum::Router;
+use axum::http::{HeaderValue, Method, header};
+use axum::serve::Listener;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{debug, info};
+
+use crate::handler::active::ActiveRequests;
+
+/// How often drain progress is logged during shutdown.
+const DRAIN_LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Inbound connection settings for `serve`.
+#[derive(Debug, Clone, Default)]
+pub struct ServerOptions {
+    /// Accept HTTP/2 with prior knowledge (h2c) in addition to HTTP/1.1.
+    /// The protocol is detected per connection, so HTTP/1.1 clients keep working.
+    pub http2: bool,
+    /// Close HTTP/1.1 connections that send no request headers within this
+    /// window, and ping HTTP/2 connections at this interval (dropping them if
+    /// the ping is not answered within the same window).
+    pub keepalive_timeout: Option<Duration>,
+    /// Requests in progress, logged while draining.
+    pub active_requests: ActiveRequests,
+}
+
+/// CORS for browser clients calling the proxy directly: answers preflights
+/// and allows `origins` (or any origin, given `*` alone) to send `GET` and
+/// `POST` with `Authorization` and `Content-Type` headers.
+pub fn cors_layer(origins: &[String]) -> Result<CorsLayer, String> {
+    let allow_origin = if origins.iter().any(|o| o == "*") {
+        if origins.len() > 1 {
+            return Err("'*' can't be combined with other origins".into());
+        }
+        AllowOrigin::any()
+    } else {
+        let origins = origins
+            .iter()
+            .map(|o| HeaderValue::from_str(o).map_err(|_| format!("'{o}' is not a valid origin")))
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE]))
+}
+
+/// Completes when the process is asked to stop, with Ctrl-C (SIGINT) or, on
+/// unix, SIGTERM as sent by container orchestrators.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
+
+/// Serves `app` on `listener` like `axum::serve`, but with control over the
+/// HTTP protocol versions and keep-alive behavior. Once `shutdown` completes,
+/// stops accepting connections, lets open ones finish their requests, and
+/// returns when they have all closed or the drain timeout is up, logging the
+/// requests still active every second meanwhile.
+pub async fn serve<L, F>(mut listener: L, app: Router, options: ServerOptions, shutdown: F)
+where
+    L: Listener,
+    L::Addr: std::fmt::Debug,
+    F: Future<Output = ()>,
+{
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    if !options.http2 {
+        builder = builder.http1_only();
+    }
+    if let Some(timeout) = options.keepalive_timeout {
+        builder
+            .http1()
+            .timer(TokioTimer::new())
+            .header_read_timeout(timeout);
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .keep_alive_interval(timeout)
+            .keep_alive_timeout(timeout);
+    }
+
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+    loop {
+        let (io, addr) = tokio::select! {
+            accepted = listener.accept() => accepted,
+            () = &mut shutdown => break,
+        };
+        let service = TowerToHyperService::new(app.clone());
+        let builder = builder.clone();
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let conn = builder.serve_connection(TokioIo::new(io), service);
+            if let Err(e) = watcher.watch(conn).await {
+                debug!(addr = ?addr, error = %e, "connection closed with error");
+            }
+        });
+    }
+
+    drop(listener);
+    info!(
+        connections = graceful.count(),
+        "shutting down, draining connections"
+    );
+    let active = options.active_requests;
+    let drained = graceful.shutdown();
+    tokio::pin!(drained);
+    let mut progress = tokio::time::interval(DRAIN_LOG_INTERVAL);
+    loop {
+        tokio::select! {
+            () = &mut drained => {
+                info!("all connections drained");
+                break;
+            }
+            _ = progress.tick() => {
+                info!(active_requests = active.count(), "draining");
+            }
+        }
+    }
+}
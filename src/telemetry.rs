@@ -0,0 +1,114 @@
+//! Spans for distributed tracing. With the `otel` feature and
+//! `--otlp-endpoint`, each request gets a span that continues the caller's
+//! W3C trace and is exported over OTLP. Otherwise the span constructors
+//! return disabled spans and logging is unchanged.
+
+use axum::http::HeaderMap;
+use tracing::Span;
+
+#[cfg(feature = "otel")]
+pub use otlp::{init_otlp, layer};
+
+/// Span covering one HTTP request to the RPC endpoint, parented to the
+/// client's trace when it sent a `traceparent` header.
+pub fn request_span(headers: &HeaderMap) -> Span {
+    if !enabled() {
+        return Span::none();
+    }
+    let span = tracing::info_span!("rpc_request");
+    #[cfg(feature = "otel")]
+    otlp::set_parent(&span, headers);
+    #[cfg(not(feature = "otel"))]
+    let _ = headers;
+    span
+}
+
+/// Span covering one JSON-RPC call, from cache lookup to response.
+pub fn call_span(method: &str) -> Span {
+    if !enabled() {
+        return Span::none();
+    }
+    tracing::info_span!("rpc_call", method = %method)
+}
+
+/// Span covering one attempt against one backend.
+pub fn upstream_span(backend: &str) -> Span {
+    if !enabled() {
+        return Span::none();
+    }
+    tracing::info_span!("upstream", backend = %backend)
+}
+
+#[cfg(feature = "otel")]
+fn enabled() -> bool {
+    otlp::ENABLED.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+#[cfg(not(feature = "otel"))]
+fn enabled() -> bool {
+    false
+}
+
+#[cfg(feature = "otel")]
+mod otlp {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use axum::http::HeaderMap;
+    use opentelemetry::global;
+    use opentelemetry::propagation::Extractor;
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::Resource;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+    use tracing::Span;
+    use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+    use tracing_subscriber::registry::LookupSpan;
+
+    pub(super) static ENABLED: AtomicBool = AtomicBool::new(false);
+
+    /// Builds a tracer provider that batches spans to the OTLP/HTTP traces
+    /// endpoint at `endpoint`, and enables request spans. The caller shuts
+    /// the provider down on exit to flush what's left.
+    pub fn init_otlp(endpoint: &str) -> Result<SdkTracerProvider, String> {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name("rpcproxy").build())
+            .build();
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        ENABLED.store(true, Ordering::Relaxed);
+        Ok(provider)
+    }
+
+    /// The `tracing` layer that turns spans into OpenTelemetry spans.
+    pub fn layer<S>(provider: &SdkTracerProvider) -> OpenTelemetryLayer<S, SdkTracer>
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer("rpcproxy"))
+    }
+
+    pub(super) fn set_parent(span: &Span, headers: &HeaderMap) {
+        let parent = global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(headers)));
+        // Fails only if no OpenTelemetry layer is installed, in which case
+        // there is nothing to parent.
+        let _ = span.set_parent(parent);
+    }
+
+    struct HeaderExtractor<'a>(&'a HeaderMap);
+
+    impl Extractor for HeaderExtractor<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key)?.to_str().ok()
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|k| k.as_str()).collect()
+        }
+    }
+}
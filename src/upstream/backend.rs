@@ -1,4 +1,6 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+use super::target::{BasicAuth, Target, TargetHeader, sanitize_url};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BackendState {
@@ -7,44 +9,161 @@ pub enum BackendState {
     Down,
 }
 
+/// Whether a call that updates a backend's health moved it to a new state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateTransition {
+    Unchanged,
+    Changed {
+        from: BackendState,
+        to: BackendState,
+    },
+}
+
+impl StateTransition {
+    pub fn between(from: BackendState, to: BackendState) -> Self {
+        if from == to {
+            Self::Unchanged
+        } else {
+            Self::Changed { from, to }
+        }
+    }
+
+    /// True only for the transition into `Down`, not while staying there.
+    pub fn went_down(self) -> bool {
+        matches!(
+            self,
+            Self::Changed {
+                to: BackendState::Down,
+                ..
+            }
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct BackendStatus {
     pub url: String,
+    /// The URL as it may appear in logs and `/status`, with secrets masked.
+    pub display_url: String,
+    pub headers: Vec<TargetHeader>,
+    pub tags: Vec<String>,
+    pub basic_auth: Option<BasicAuth>,
     pub state: BackendState,
     pub consecutive_errors: u32,
     pub consecutive_successes: u32,
     pub last_error_at: Option<Instant>,
     pub last_success_at: Option<Instant>,
+    /// When the backend last went `Down`; cleared once it recovers.
+    pub down_since: Option<Instant>,
+    /// When the backend last came back from `Down`; `None` if it never went
+    /// down. Passing errors that leave it `Degraded` don't reset it.
+    pub healthy_since: Option<Instant>,
+    /// `eth_syncing` last reported the node catching up. A syncing backend
+    /// is at best `Degraded`, however well it answers.
+    pub syncing: bool,
     pub latest_block: Option<u64>,
+    /// When `latest_block` last advanced. A stalled chain leaves this behind
+    /// even though probes keep succeeding.
+    pub latest_block_at: Option<Instant>,
+    /// When a probe last reported a block, whether or not it advanced.
+    pub block_seen_at: Option<Instant>,
+    /// Skip this backend until then; set when it answers HTTP 429.
+    pub cooldown_until: Option<Instant>,
     pub avg_latency_ms: f64,
     pub total_requests: u64,
     pub total_errors: u64,
+    /// Bytes of upstream response bodies received for proxied requests.
+    pub total_response_bytes: u64,
+    /// Number of response bodies counted in `total_response_bytes`.
+    pub sized_responses: u64,
+    /// Successful calls slower than `--slow-query-threshold-ms`.
+    pub slow_queries: u64,
     pub started_at: Instant,
 }
 
 impl BackendStatus {
     pub fn new(url: String) -> Self {
+        Self::from_target(Target::from(url))
+    }
+
+    pub fn from_target(target: Target) -> Self {
         Self {
-            url,
+            display_url: sanitize_url(&target.url),
+            url: target.url,
+            headers: target.headers,
+            tags: target.tags,
+            basic_auth: target.basic_auth,
             state: BackendState::Healthy,
             consecutive_errors: 0,
             consecutive_successes: 0,
             last_error_at: None,
             last_success_at: None,
+            down_since: None,
+            healthy_since: None,
+            syncing: false,
             latest_block: None,
+            latest_block_at: None,
+            block_seen_at: None,
+            cooldown_until: None,
             avg_latency_ms: 0.0,
             total_requests: 0,
             total_errors: 0,
+            total_response_bytes: 0,
+            sized_responses: 0,
+            slow_queries: 0,
             started_at: Instant::now(),
         }
     }
 
+    /// The target this backend was configured from, for probing and forwarding.
+    pub fn target(&self) -> Target {
+        Target {
+            url: self.url.clone(),
+            headers: self.headers.clone(),
+            tags: self.tags.clone(),
+            basic_auth: self.basic_auth.clone(),
+        }
+    }
+
+    /// Records a block number seen by a health probe.
+    pub fn record_block(&mut self, block: u64) {
+        self.block_seen_at = Some(Instant::now());
+        if self.latest_block != Some(block) {
+            self.latest_block = Some(block);
+            self.latest_block_at = Some(Instant::now());
+        }
+    }
+
+    /// Whether the backend is `Healthy` and, if it was ever `Down`, has been
+    /// back for at least `delay`.
+    pub fn is_settled(&self, delay: Duration) -> bool {
+        self.state == BackendState::Healthy
+            && self.healthy_since.is_none_or(|at| at.elapsed() >= delay)
+    }
+
+    /// Whether a block has been reported, within `window` if one is given.
+    /// A backend whose probes stopped succeeding loses it once the window
+    /// passes, rather than counting as synced on an old observation forever.
+    pub fn has_fresh_block(&self, window: Option<Duration>) -> bool {
+        match window {
+            Some(window) => self.block_seen_at.is_some_and(|at| at.elapsed() <= window),
+            None => self.latest_block.is_some(),
+        }
+    }
+
     pub fn record_success(&mut self, latency_ms: f64) {
         self.total_requests += 1;
         self.consecutive_errors = 0;
         self.consecutive_successes += 1;
         self.last_success_at = Some(Instant::now());
-        self.state = BackendState::Healthy;
+        self.state = if self.syncing {
+            BackendState::Degraded
+        } else {
+            BackendState::Healthy
+        };
+        if self.down_since.take().is_some() {
+            self.healthy_since = Some(Instant::now());
+        }
         if self.avg_latency_ms == 0.0 {
             self.avg_latency_ms = latency_ms;
         } else {
@@ -52,7 +171,43 @@ impl BackendStatus {
         }
     }
 
-    pub fn record_error(&mut self) {
+    /// Records the body size of a response received from this backend.
+    pub fn record_response_bytes(&mut self, bytes: u64) {
+        self.total_response_bytes += bytes;
+        self.sized_responses += 1;
+    }
+
+    pub fn record_slow_query(&mut self) {
+        self.slow_queries += 1;
+    }
+
+    /// Mean response body size, or 0 if nothing has been received yet.
+    pub fn avg_response_bytes(&self) -> f64 {
+        if self.sized_responses == 0 {
+            0.0
+        } else {
+            self.total_response_bytes as f64 / self.sized_responses as f64
+        }
+    }
+
+    /// Records an HTTP 429. Being rate-limited says nothing about the
+    /// backend's health, so it does not count toward `Down`; instead the
+    /// backend is skipped for `cooldown`, if the backend gave one.
+    pub fn record_rate_limited(&mut self, cooldown: Option<Duration>) {
+        self.total_requests += 1;
+        self.cooldown_until = cooldown.map(|d| Instant::now() + d);
+    }
+
+    /// Time left before a rate-limited backend may be used again.
+    pub fn cooldown_remaining(&self) -> Option<Duration> {
+        self.cooldown_until
+            .map(|until| until.saturating_duration_since(Instant::now()))
+            .filter(|d| !d.is_zero())
+    }
+
+    /// Records a failed call and reports whether it changed the state.
+    pub fn record_error(&mut self) -> StateTransition {
+        let from = self.state;
         self.total_requests += 1;
         self.total_errors += 1;
         self.consecutive_successes = 0;
@@ -63,6 +218,11 @@ impl BackendStatus {
         } else {
             self.state = BackendState::Degraded;
         }
+        let transition = StateTransition::between(from, self.state);
+        if transition.went_down() {
+            self.down_since = Some(Instant::now());
+        }
+        transition
     }
 }
 
@@ -71,9 +231,19 @@ pub struct BackendHealthInfo {
     pub url: String,
     pub priority: usize,
     pub state: String,
+    pub tags: Vec<String>,
     pub latency_ms: f64,
     pub latest_block: Option<u64>,
     pub total_requests: u64,
     pub total_errors: u64,
+    /// Seconds until a rate-limited backend is used again.
+    pub cooldown_remaining_secs: Option<u64>,
+    /// Seconds since a `Down` backend went down.
+    pub down_for_secs: Option<u64>,
+    /// Whether `eth_syncing` last reported the node catching up.
+    pub syncing: bool,
+    pub total_response_bytes: u64,
+    pub avg_response_bytes: f64,
+    pub slow_query_count: u64,
     pub uptime_secs: u64,
 }
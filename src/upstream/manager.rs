@@ -1,38 +1,270 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::pin::Pin;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use reqwest::Client;
+use bytes::Bytes;
+use futures_util::future::join_all;
+use futures_util::stream::{self, Stream, StreamExt};
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
 use tokio::sync::{Notify, RwLock};
-use tracing::{debug, error, warn};
+use tracing::{Instrument, debug, error, info, warn};
 
-use crate::error::RpcProxyError;
+use crate::cache::policy as cache_policy;
+use crate::error::{FailedAttempt, RpcProxyError};
 use crate::jsonrpc::{JsonRpcRequest, JsonRpcResponse};
+use crate::telemetry;
 
-use super::backend::{BackendHealthInfo, BackendState, BackendStatus};
+use super::backend::{BackendHealthInfo, BackendState, BackendStatus, StateTransition};
+use super::mirror::{Mirror, MirrorInfo};
+use super::target::{Target, TargetHeader, sanitize_url};
+use super::webhook::StateWebhook;
+
+/// Tuning for the upstream HTTP client. `Default` matches the proxy's
+/// built-in behavior.
+#[derive(Debug, Clone)]
+pub struct UpstreamOptions {
+    pub request_timeout: Duration,
+    /// Limit on establishing a connection, within `request_timeout`.
+    pub connect_timeout: Option<Duration>,
+    /// Per-method replacements for `request_timeout`, for calls such as
+    /// traces that legitimately take longer.
+    pub method_timeouts: HashMap<String, Duration>,
+    /// Methods answered only once two backends agree on the result.
+    pub quorum_methods: HashSet<String>,
+    pub pool_max_idle_per_host: usize,
+    pub pool_idle_timeout: Duration,
+    /// Speak HTTP/2 to upstreams without negotiating (prior knowledge).
+    pub http2: bool,
+    /// HTTP or SOCKS5 proxy for every upstream connection.
+    pub upstream_proxy: Option<reqwest::Proxy>,
+    /// Log and report backend URLs verbatim instead of sanitizing them.
+    pub show_full_urls: bool,
+    /// Shadow backend receiving a copy of read-only traffic for comparison.
+    pub mirror_target: Option<Target>,
+    /// Fraction (0.0–1.0) of eligible requests copied to `mirror_target`.
+    pub mirror_rate: f64,
+    /// Minimum time a backend is skipped after answering HTTP 429. A longer
+    /// `Retry-After` from the backend wins.
+    pub rate_limit_cooldown: Duration,
+    /// Methods pinned to a subset of backends, as indices into the target
+    /// list. Methods without a route may use every backend.
+    pub routes: HashMap<String, Vec<usize>>,
+    /// Methods pinned to the backends carrying any of the given tags, in
+    /// addition to those listed in `routes`.
+    pub route_tags: HashMap<String, Vec<String>>,
+    /// Retry one backend once more after every backend has failed.
+    pub last_resort: bool,
+    /// Upper bound of the random delay before the last-resort attempt.
+    pub retry_jitter: Duration,
+    /// Budget for one request across all backends, including the last
+    /// resort. Once spent, the remaining backends are abandoned.
+    pub total_timeout: Option<Duration>,
+    /// Successful calls slower than this are logged and counted per backend.
+    pub slow_query_threshold: Option<Duration>,
+    /// URL that receives a JSON event when a backend changes state.
+    pub state_change_webhook: Option<String>,
+    /// Transitions of one backend within this window are sent as one event.
+    pub state_change_debounce: Duration,
+    /// After startup, backends that haven't passed a health probe yet are
+    /// tried only after those that have, for up to this long.
+    pub startup_grace: Duration,
+    /// A backend that returns to `Healthy` is tried only after the settled
+    /// ones until it has stayed healthy this long.
+    pub failback_delay: Duration,
+    /// A backend's block counts toward readiness only this long after a
+    /// probe last reported it.
+    pub block_freshness: Option<Duration>,
+    /// Upstream bodies are read until they exceed this many bytes, then
+    /// abandoned with `ResponseTooLarge`. Streamed bodies are not limited.
+    pub max_response_bytes: Option<usize>,
+    /// Also probe `eth_syncing` in health checks and keep syncing backends
+    /// `Degraded`.
+    pub check_syncing: bool,
+}
+
+impl Default for UpstreamOptions {
+    fn default() -> Self {
+        Self {
+            request_timeout: Duration::from_secs(10),
+            connect_timeout: None,
+            method_timeouts: HashMap::new(),
+            quorum_methods: HashSet::new(),
+            pool_max_idle_per_host: 20,
+            pool_idle_timeout: Duration::from_secs(90),
+            http2: false,
+            upstream_proxy: None,
+            show_full_urls: false,
+            mirror_target: None,
+            mirror_rate: 1.0,
+            rate_limit_cooldown: Duration::from_secs(2),
+            routes: HashMap::new(),
+            route_tags: HashMap::new(),
+            last_resort: true,
+            retry_jitter: Duration::ZERO,
+            total_timeout: None,
+            slow_query_threshold: None,
+            state_change_webhook: None,
+            state_change_debounce: Duration::from_secs(5),
+            startup_grace: Duration::ZERO,
+            failback_delay: Duration::ZERO,
+            block_freshness: None,
+            max_response_bytes: None,
+            check_syncing: false,
+        }
+    }
+}
+
+/// A response body passed through from a backend without buffering.
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, reqwest::Error>> + Send>>;
+
+/// What `send_request_streaming` got back from a backend.
+pub enum UpstreamReply {
+    /// The body was small enough to buffer and validate.
+    Parsed(JsonRpcResponse),
+    /// The body exceeded the threshold and is passed through as-is.
+    Stream(ByteStream),
+}
 
 pub struct UpstreamManager {
     backends: Vec<Arc<RwLock<BackendStatus>>>,
+    /// Each backend's URL as displayed, in priority order. Fixed at startup,
+    /// so it can be read without locking the backends.
+    backend_urls: Vec<String>,
     client: Client,
     health_notify: Arc<Notify>,
+    mirror: Option<Mirror>,
+    rate_limit_cooldown: Duration,
+    routes: HashMap<String, Vec<usize>>,
+    /// The client's timeout, which applies to methods not in `method_timeouts`.
+    request_timeout: Duration,
+    method_timeouts: HashMap<String, Duration>,
+    quorum_methods: HashSet<String>,
+    last_resort: bool,
+    retry_jitter: Duration,
+    total_timeout: Option<Duration>,
+    slow_query_threshold: Option<Duration>,
+    state_webhook: Option<StateWebhook>,
+    /// End of the startup grace period, if one is configured.
+    grace_until: Option<Instant>,
+    failback_delay: Duration,
+    block_freshness: Option<Duration>,
+    max_response_bytes: Option<usize>,
+    upstream_proxy: Option<reqwest::Proxy>,
+    check_syncing: bool,
+    /// Backends tagged `archive`, tried first for archive methods that have
+    /// no explicit route.
+    archive_backends: Vec<usize>,
 }
 
+/// Tag marking a backend as an archive node.
+const ARCHIVE_TAG: &str = "archive";
+
 impl UpstreamManager {
     pub fn new(urls: Vec<String>, request_timeout: Duration) -> Self {
-        let client = Client::builder()
-            .timeout(request_timeout)
-            .pool_max_idle_per_host(20)
-            .build()
-            .expect("failed to build HTTP client");
+        Self::with_options(
+            urls.into_iter().map(Target::from).collect(),
+            UpstreamOptions {
+                request_timeout,
+                ..Default::default()
+            },
+        )
+    }
 
-        let backends = urls
+    pub fn with_options(targets: Vec<Target>, options: UpstreamOptions) -> Self {
+        let mut builder = Client::builder()
+            .timeout(options.request_timeout)
+            .pool_max_idle_per_host(options.pool_max_idle_per_host)
+            .pool_idle_timeout(options.pool_idle_timeout);
+        if options.http2 {
+            builder = builder.http2_prior_knowledge();
+        }
+        if let Some(timeout) = options.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(proxy) = &options.upstream_proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        let client = builder.build().expect("failed to build HTTP client");
+
+        // Routes name backends by their position in the original list.
+        let (targets, positions) = dedup_targets(targets, options.show_full_urls);
+        let mut routes = options.routes;
+        for indices in routes.values_mut() {
+            for i in indices.iter_mut() {
+                *i = positions.get(*i).copied().unwrap_or(*i);
+            }
+        }
+        for (method, tags) in options.route_tags {
+            let tagged = targets
+                .iter()
+                .enumerate()
+                .filter(|(_, t)| t.tags.iter().any(|tag| tags.contains(tag)))
+                .map(|(i, _)| i);
+            routes.entry(method).or_default().extend(tagged);
+        }
+
+        let archive_backends = targets
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.tags.iter().any(|tag| tag == ARCHIVE_TAG))
+            .map(|(i, _)| i)
+            .collect();
+
+        let backends: Vec<_> = targets
             .into_iter()
-            .map(|url| Arc::new(RwLock::new(BackendStatus::new(url))))
+            .map(|target| {
+                let mut backend = BackendStatus::from_target(target);
+                if options.show_full_urls {
+                    backend.display_url = backend.url.clone();
+                }
+                backend
+            })
             .collect();
+        let backend_urls = backends.iter().map(|b| b.display_url.clone()).collect();
+        let backends = backends
+            .into_iter()
+            .map(|backend| Arc::new(RwLock::new(backend)))
+            .collect();
+
+        let mirror = options.mirror_target.map(|target| {
+            Mirror::new(
+                target,
+                options.mirror_rate,
+                options.max_response_bytes,
+                options.show_full_urls,
+            )
+        });
 
         Self {
             backends,
+            backend_urls,
             client,
             health_notify: Arc::new(Notify::new()),
+            mirror,
+            rate_limit_cooldown: options.rate_limit_cooldown,
+            routes,
+            request_timeout: options.request_timeout,
+            method_timeouts: options.method_timeouts,
+            quorum_methods: options.quorum_methods,
+            last_resort: options.last_resort,
+            retry_jitter: options.retry_jitter,
+            total_timeout: options.total_timeout,
+            slow_query_threshold: options.slow_query_threshold,
+            state_webhook: options
+                .state_change_webhook
+                .map(|url| StateWebhook::new(url, options.state_change_debounce)),
+            grace_until: (!options.startup_grace.is_zero())
+                .then(|| Instant::now() + options.startup_grace),
+            failback_delay: options.failback_delay,
+            block_freshness: options.block_freshness,
+            max_response_bytes: options.max_response_bytes,
+            upstream_proxy: options.upstream_proxy,
+            check_syncing: options.check_syncing,
+            archive_backends,
         }
     }
 
@@ -40,83 +272,647 @@ impl UpstreamManager {
         &self,
         request: &JsonRpcRequest,
     ) -> Result<JsonRpcResponse, RpcProxyError> {
-        for backend_lock in &self.backends {
-            let (url, state) = {
+        self.send_request_with_affinity(request, None, &[]).await
+    }
+
+    /// Like `send_request`, but requests with the same `affinity` key are
+    /// sent to the same backend while it is usable, for node-local state such
+    /// as filters. Other backends remain failover targets. `forwarded` headers
+    /// from the client (e.g. `traceparent`) are sent along with each
+    /// backend's own.
+    pub async fn send_request_with_affinity(
+        &self,
+        request: &JsonRpcRequest,
+        affinity: Option<&str>,
+        forwarded: &[TargetHeader],
+    ) -> Result<JsonRpcResponse, RpcProxyError> {
+        if self.quorum_methods.contains(&request.method) {
+            return self.send_quorum(request, forwarded).await;
+        }
+        let max_bytes = self.max_response_bytes;
+        let timeout = self.timeout_for(&request.method);
+        let response = self
+            .try_backends(request, affinity, |client, mut target| async move {
+                target.headers.extend_from_slice(forwarded);
+                forward_to_backend(&client, &target, request, timeout, max_bytes).await
+            })
+            .await?;
+        self.mirror_request(request, &response);
+        Ok(response)
+    }
+
+    /// Like `send_request`, but a response body larger than `threshold` bytes
+    /// is handed back as a stream instead of being buffered and parsed.
+    /// Failover only happens before the stream is returned.
+    pub async fn send_request_streaming(
+        &self,
+        request: &JsonRpcRequest,
+        threshold: usize,
+        affinity: Option<&str>,
+        forwarded: &[TargetHeader],
+    ) -> Result<UpstreamReply, RpcProxyError> {
+        if self.quorum_methods.contains(&request.method) {
+            let response = self.send_quorum(request, forwarded).await?;
+            return Ok(UpstreamReply::Parsed(response));
+        }
+        let max_bytes = self.max_response_bytes;
+        let timeout = self.timeout_for(&request.method);
+        let reply = self
+            .try_backends(request, affinity, |client, mut target| async move {
+                target.headers.extend_from_slice(forwarded);
+                forward_streaming(&client, &target, request, threshold, timeout, max_bytes).await
+            })
+            .await?;
+        if let UpstreamReply::Parsed(response) = &reply {
+            self.mirror_request(request, response);
+        }
+        Ok(reply)
+    }
+
+    /// Sends `requests` to one backend as a single JSON-RPC batch and returns
+    /// the responses in request order. Returns `None` when the batch has to go
+    /// element by element instead: its calls don't share a route, their ids
+    /// aren't distinct, or the backend answered with anything but one response
+    /// per call (e.g. it doesn't support batches). If every backend failed,
+    /// the error is returned, since the batch may already have been received.
+    pub async fn send_batch(
+        &self,
+        requests: &[JsonRpcRequest],
+        affinity: Option<&str>,
+        forwarded: &[TargetHeader],
+    ) -> Result<Option<Vec<JsonRpcResponse>>, RpcProxyError> {
+        if requests
+            .iter()
+            .any(|r| self.quorum_methods.contains(&r.method))
+        {
+            return Ok(None);
+        }
+        let Some((first, rest)) = requests.split_first() else {
+            return Ok(None);
+        };
+        let route = self.candidates(first);
+        if rest.iter().any(|r| self.candidates(r) != route) {
+            return Ok(None);
+        }
+        if requests
+            .iter()
+            .enumerate()
+            .any(|(i, r)| requests[..i].iter().any(|other| other.id == r.id))
+        {
+            return Ok(None);
+        }
+
+        let max_bytes = self.max_response_bytes;
+        // The batch gets as long as its slowest method may take, counting
+        // methods without an override at the default timeout.
+        let timeout = requests
+            .iter()
+            .map(|r| self.timeout_for(&r.method).unwrap_or(self.request_timeout))
+            .max();
+        let result = self
+            .try_backends(first, affinity, |client, mut target| async move {
+                target.headers.extend_from_slice(forwarded);
+                forward_batch(&client, &target, requests, timeout, max_bytes).await
+            })
+            .await;
+        let responses = match result {
+            Ok(Some(responses)) => responses,
+            Ok(None) => {
+                debug!(
+                    calls = requests.len(),
+                    "upstream batch not answered per call, sending individually"
+                );
+                return Ok(None);
+            }
+            Err(e) => {
+                warn!(calls = requests.len(), error = %e, "upstream batch failed");
+                return Err(e);
+            }
+        };
+        for (request, response) in requests.iter().zip(&responses) {
+            self.mirror_request(request, response);
+        }
+        Ok(Some(responses))
+    }
+
+    /// Answers a `--quorum-method` call: asks the first two usable backends
+    /// at once, then one more backend at a time until two of them give the
+    /// same answer, which is returned. Failed backends don't count. A method
+    /// routed to a single backend takes its answer alone. Gives up with
+    /// `NoQuorum` once the backends run out, or with `AllUpstreamsFailed` if
+    /// none answered or the total timeout is spent. If every backend is
+    /// rate-limited, returns `RateLimited` as `try_backends` does.
+    async fn send_quorum(
+        &self,
+        request: &JsonRpcRequest,
+        forwarded: &[TargetHeader],
+    ) -> Result<JsonRpcResponse, RpcProxyError> {
+        let deadline = self.total_timeout.map(|t| Instant::now() + t);
+        let candidates = self.candidates(request);
+        let needed = candidates.len().min(2);
+        let mut all_rate_limited = !candidates.is_empty();
+        let mut retry_after: Option<Duration> = None;
+        let mut note_retry_after = |delay: Option<Duration>| {
+            if let Some(delay) = delay {
+                retry_after = Some(retry_after.map_or(delay, |r| r.min(delay)));
+            }
+        };
+
+        let mut usable = Vec::new();
+        for &i in &candidates {
+            let b = self.backends[i].read().await;
+            if b.state == BackendState::Down {
+                all_rate_limited = false;
+            } else if let Some(cooldown) = b.cooldown_remaining() {
+                note_retry_after(Some(cooldown));
+            } else {
+                usable.push(i);
+            }
+        }
+        let mut remaining = usable.into_iter();
+
+        let mut answers: Vec<(usize, JsonRpcResponse)> = Vec::new();
+        let mut attempts = Vec::new();
+        let mut asking: Vec<usize> = remaining.by_ref().take(needed).collect();
+        while !asking.is_empty() {
+            let calls = asking
+                .iter()
+                .map(|&i| async move { (i, self.call_backend(i, request, forwarded).await) });
+            let Some(results) = within(deadline, join_all(calls)).await else {
+                warn!(method = %request.method, "total request timeout reached, giving up");
+                for &i in &asking {
+                    attempts.push(FailedAttempt {
+                        backend: self.backends[i].read().await.display_url.clone(),
+                        error: "total request timeout reached".into(),
+                    });
+                }
+                return Err(RpcProxyError::AllUpstreamsFailed { attempts });
+            };
+            for (i, result) in results {
+                let e = match result {
+                    Ok(response) => {
+                        answers.push((i, response));
+                        continue;
+                    }
+                    Err(e @ RpcProxyError::ResponseTooLarge { .. }) => return Err(e),
+                    Err(e) => e,
+                };
+                let backend = self.backends[i].read().await;
+                if let RpcProxyError::RateLimited { retry_after: delay } = e {
+                    note_retry_after(self.cooldown_after_429(delay));
+                } else {
+                    all_rate_limited = false;
+                }
+                attempts.push(FailedAttempt {
+                    backend: backend.display_url.clone(),
+                    error: e.to_string(),
+                });
+            }
+
+            let agreed = answers.iter().position(|(_, a)| {
+                answers.iter().filter(|(_, b)| same_answer(a, b)).count() >= needed
+            });
+            if let Some(n) = agreed {
+                let (i, response) = answers.swap_remove(n);
+                note_served_by(&self.backends[i].read().await.display_url);
+                self.mirror_request(request, &response);
+                return Ok(response);
+            }
+            if answers.len() >= 2 {
+                warn!(method = %request.method, "backends disagree, asking another");
+            }
+            asking = remaining.next().into_iter().collect();
+        }
+
+        if answers.is_empty() {
+            if all_rate_limited {
+                warn!("all upstream backends are rate limited");
+                return Err(RpcProxyError::RateLimited { retry_after });
+            }
+            error!("all upstream backends failed");
+            return Err(RpcProxyError::AllUpstreamsFailed { attempts });
+        }
+        warn!(method = %request.method, answers = answers.len(), "no quorum among backends");
+        Err(RpcProxyError::NoQuorum {
+            answers: answers.len(),
+        })
+    }
+
+    /// Sends `request` to backend `i` alone, recording the outcome on it.
+    async fn call_backend(
+        &self,
+        i: usize,
+        request: &JsonRpcRequest,
+        forwarded: &[TargetHeader],
+    ) -> Result<JsonRpcResponse, RpcProxyError> {
+        let backend_lock = &self.backends[i];
+        let (mut target, display_url) = {
+            let backend = backend_lock.read().await;
+            (backend.target(), backend.display_url.clone())
+        };
+        target.headers.extend_from_slice(forwarded);
+        let timeout = self.timeout_for(&request.method);
+        let start = Instant::now();
+        let result = forward_to_backend(
+            &self.client,
+            &target,
+            request,
+            timeout,
+            self.max_response_bytes,
+        )
+        .instrument(telemetry::upstream_span(&display_url))
+        .await;
+
+        let mut backend = backend_lock.write().await;
+        let old_state = backend.state;
+        match result {
+            Ok((response, bytes)) => {
+                let latency = start.elapsed().as_secs_f64() * 1000.0;
+                backend.record_success(latency);
+                backend.record_response_bytes(bytes as u64);
+                self.check_slow_query(&mut backend, request, latency);
+                self.note_transition(&backend, StateTransition::between(old_state, backend.state));
+                Ok(response)
+            }
+            Err(RpcProxyError::RateLimited { retry_after }) => {
+                backend.record_rate_limited(self.cooldown_after_429(retry_after));
+                warn!(backend = %display_url, retry_after = ?retry_after, "upstream rate limited");
+                Err(RpcProxyError::RateLimited { retry_after })
+            }
+            Err(e @ RpcProxyError::ResponseTooLarge { .. }) => {
+                warn!(backend = %display_url, method = %request.method, error = %e, "upstream response too large");
+                Err(e)
+            }
+            Err(e) => {
+                let transition = backend.record_error();
+                self.note_transition(&backend, transition);
+                warn!(backend = %display_url, error = %e, state = ?backend.state, "upstream error");
+                if transition.went_down() {
+                    self.health_notify.notify_one();
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// How long to skip a backend that answered 429 with `retry_after`: the
+    /// longer of that and the configured cooldown, or `None` if both are zero.
+    fn cooldown_after_429(&self, retry_after: Option<Duration>) -> Option<Duration> {
+        let cooldown = retry_after.map_or(self.rate_limit_cooldown, |d| {
+            d.max(self.rate_limit_cooldown)
+        });
+        Some(cooldown).filter(|c| !c.is_zero())
+    }
+
+    /// Tries each backend routed for the request's method in priority order,
+    /// skipping those that are down or cooling down after a 429, then (unless
+    /// disabled) makes a last-resort attempt on the one most likely to have
+    /// recovered. If every backend is rate-limited, returns `RateLimited` with
+    /// the shortest known delay instead. Within `pin_backend`, the backend
+    /// pinned there is tried first; otherwise, with an `affinity` key, the
+    /// backend it hashes to is. Gives up with `AllUpstreamsFailed` once
+    /// the total timeout, if any, is spent. `forward` returns the reply and
+    /// its body size in bytes.
+    async fn try_backends<T, F, Fut>(
+        &self,
+        request: &JsonRpcRequest,
+        affinity: Option<&str>,
+        forward: F,
+    ) -> Result<T, RpcProxyError>
+    where
+        F: Fn(Client, Target) -> Fut,
+        Fut: Future<Output = Result<(T, usize), RpcProxyError>>,
+    {
+        let deadline = self.total_timeout.map(|t| Instant::now() + t);
+        let mut attempts = Vec::new();
+        let candidates = self.prefer_probed(self.candidates(request)).await;
+        let candidates = self.prefer_settled(candidates).await;
+        // Whether every backend so far was rate-limited, and the shortest
+        // delay any of them asked for.
+        let mut all_rate_limited = !candidates.is_empty();
+        let mut retry_after: Option<Duration> = None;
+        let mut note_retry_after = |delay: Option<Duration>| {
+            if let Some(delay) = delay {
+                retry_after = Some(retry_after.map_or(delay, |r| r.min(delay)));
+            }
+        };
+
+        let pinned = PINNED
+            .try_with(Cell::get)
+            .ok()
+            .flatten()
+            .filter(|i| candidates.contains(i));
+        let preferred = match (pinned, affinity) {
+            (Some(i), _) => Some(i),
+            (None, Some(key)) => self.affinity_backend(key, &candidates).await,
+            (None, None) => None,
+        };
+        let order = preferred
+            .into_iter()
+            .chain(candidates.iter().copied().filter(|&i| Some(i) != preferred));
+
+        for i in order {
+            let backend_lock = &self.backends[i];
+            let (target, display_url, state, cooldown) = {
                 let backend = backend_lock.read().await;
-                (backend.url.clone(), backend.state)
+                (
+                    backend.target(),
+                    backend.display_url.clone(),
+                    backend.state,
+                    backend.cooldown_remaining(),
+                )
             };
 
             if state == BackendState::Down {
-                debug!(backend = %url, "skipping down backend");
+                debug!(backend = %display_url, "skipping down backend");
+                all_rate_limited = false;
+                continue;
+            }
+            if cooldown.is_some() {
+                debug!(backend = %display_url, "skipping rate-limited backend");
+                note_retry_after(cooldown);
                 continue;
             }
 
             let start = Instant::now();
-            match self.forward_to_backend(&url, request).await {
-                Ok(response) => {
+            let Some(result) = within(
+                deadline,
+                forward(self.client.clone(), target)
+                    .instrument(telemetry::upstream_span(&display_url)),
+            )
+            .await
+            else {
+                warn!(backend = %display_url, "total request timeout reached, giving up");
+                attempts.push(FailedAttempt {
+                    backend: display_url,
+                    error: "total request timeout reached".into(),
+                });
+                return Err(RpcProxyError::AllUpstreamsFailed { attempts });
+            };
+            match result {
+                Ok((reply, bytes)) => {
                     let latency = start.elapsed().as_secs_f64() * 1000.0;
                     let mut backend = backend_lock.write().await;
+                    let old_state = backend.state;
                     backend.record_success(latency);
-                    debug!(backend = %url, latency_ms = %latency, "upstream success");
-                    return Ok(response);
+                    self.note_transition(
+                        &backend,
+                        StateTransition::between(old_state, backend.state),
+                    );
+                    backend.record_response_bytes(bytes as u64);
+                    self.check_slow_query(&mut backend, request, latency);
+                    debug!(backend = %display_url, latency_ms = %latency, "upstream success");
+                    note_served_by(&display_url);
+                    note_pin(i);
+                    return Ok(reply);
+                }
+                Err(RpcProxyError::RateLimited { retry_after: delay }) => {
+                    let cooldown = self.cooldown_after_429(delay);
+                    let mut backend = backend_lock.write().await;
+                    backend.record_rate_limited(cooldown);
+                    note_retry_after(cooldown);
+                    warn!(backend = %display_url, retry_after = ?delay, "upstream rate limited, trying next");
+                    attempts.push(FailedAttempt {
+                        backend: display_url,
+                        error: RpcProxyError::RateLimited { retry_after: delay }.to_string(),
+                    });
+                }
+                // Any backend would send the same oversized answer, and the
+                // backend itself is fine, so neither fail over nor count it.
+                Err(e @ RpcProxyError::ResponseTooLarge { .. }) => {
+                    warn!(backend = %display_url, method = %request.method, error = %e, "upstream response too large");
+                    return Err(e);
                 }
                 Err(e) => {
+                    all_rate_limited = false;
                     let mut backend = backend_lock.write().await;
-                    backend.record_error();
-                    let state = backend.state;
-                    warn!(backend = %url, error = %e, state = ?state, "upstream error, trying next");
-                    if state == BackendState::Down {
+                    let transition = backend.record_error();
+                    self.note_transition(&backend, transition);
+                    warn!(backend = %display_url, error = %e, state = ?backend.state, "upstream error, trying next");
+                    // Only the transition wakes the checker; a backend that
+                    // keeps failing while Down would otherwise trigger a
+                    // probe round per request.
+                    if transition.went_down() {
                         self.health_notify.notify_one();
                     }
+                    attempts.push(FailedAttempt {
+                        backend: display_url,
+                        error: e.to_string(),
+                    });
                 }
             }
         }
 
-        // All backends failed — last resort: try the first one anyway
-        if let Some(backend_lock) = self.backends.first() {
-            let url = backend_lock.read().await.url.clone();
-            warn!(backend = %url, "all backends failed, last-resort attempt on primary");
-            let start = Instant::now();
-            if let Ok(response) = self.forward_to_backend(&url, request).await {
-                let latency = start.elapsed().as_secs_f64() * 1000.0;
-                let mut backend = backend_lock.write().await;
-                backend.record_success(latency);
-                return Ok(response);
+        // Retrying a rate-limited backend would only add to its load.
+        if all_rate_limited {
+            warn!("all upstream backends are rate limited");
+            return Err(RpcProxyError::RateLimited { retry_after });
+        }
+
+        // All backends failed — last resort: try the likeliest one anyway
+        if self.last_resort
+            && let Some(i) = self.last_resort_backend(&candidates).await
+        {
+            let backend_lock = &self.backends[i];
+            let (target, display_url) = {
+                let backend = backend_lock.read().await;
+                (backend.target(), backend.display_url.clone())
+            };
+            let delay = retry_jitter(self.retry_jitter);
+            warn!(backend = %display_url, delay_ms = delay.as_millis() as u64, "all backends failed, last-resort attempt");
+            // The sleep counts against the total timeout like any attempt.
+            if !delay.is_zero() {
+                within(deadline, tokio::time::sleep(delay)).await;
             }
+            let start = Instant::now();
+            let error = match within(
+                deadline,
+                forward(self.client.clone(), target)
+                    .instrument(telemetry::upstream_span(&display_url)),
+            )
+            .await
+            {
+                Some(Ok((reply, bytes))) => {
+                    let latency = start.elapsed().as_secs_f64() * 1000.0;
+                    let mut backend = backend_lock.write().await;
+                    let old_state = backend.state;
+                    backend.record_success(latency);
+                    self.note_transition(
+                        &backend,
+                        StateTransition::between(old_state, backend.state),
+                    );
+                    backend.record_response_bytes(bytes as u64);
+                    self.check_slow_query(&mut backend, request, latency);
+                    note_served_by(&display_url);
+                    note_pin(i);
+                    return Ok(reply);
+                }
+                Some(Err(e)) => e.to_string(),
+                None => "total request timeout reached".into(),
+            };
+            attempts.push(FailedAttempt {
+                backend: display_url,
+                error,
+            });
         }
 
         error!("all upstream backends failed");
-        Err(RpcProxyError::AllUpstreamsFailed)
+        Err(RpcProxyError::AllUpstreamsFailed { attempts })
     }
 
-    async fn forward_to_backend(
+    /// Reports a state change to the webhook, if one is configured.
+    fn note_transition(&self, backend: &BackendStatus, transition: StateTransition) {
+        if let StateTransition::Changed { from, to } = transition
+            && let Some(webhook) = &self.state_webhook
+        {
+            webhook.notify(&backend.display_url, from, to);
+        }
+    }
+
+    /// Counts and logs a successful call slower than the slow-query threshold.
+    fn check_slow_query(
         &self,
-        url: &str,
+        backend: &mut BackendStatus,
         request: &JsonRpcRequest,
-    ) -> Result<JsonRpcResponse, RpcProxyError> {
-        let body = serde_json::to_string(request)?;
-
-        let resp = self
-            .client
-            .post(url)
-            .header("content-type", "application/json")
-            .body(body)
-            .send()
-            .await
-            .map_err(|e| RpcProxyError::UpstreamRequest(e.to_string()))?;
+        latency_ms: f64,
+    ) {
+        let Some(threshold) = self.slow_query_threshold else {
+            return;
+        };
+        if latency_ms <= threshold.as_secs_f64() * 1000.0 {
+            return;
+        }
+        backend.record_slow_query();
+        let params_bytes = serde_json::to_string(&request.params).map_or(0, |p| p.len());
+        warn!(
+            backend = %backend.display_url,
+            method = %request.method,
+            latency_ms = %latency_ms,
+            params_bytes,
+            "slow upstream query"
+        );
+    }
 
-        if !resp.status().is_success() {
-            return Err(RpcProxyError::UpstreamHttp(resp.status().as_u16()));
+    /// Picks the candidate for the last-resort attempt: the one with the
+    /// fewest consecutive errors, then the one whose last error is oldest,
+    /// then the highest priority. Backends cooling down after a 429 are left
+    /// alone, so `None` if every candidate is.
+    async fn last_resort_backend(&self, candidates: &[usize]) -> Option<usize> {
+        let mut best: Option<((u32, Option<Instant>), usize)> = None;
+        for &i in candidates {
+            let b = self.backends[i].read().await;
+            if b.cooldown_remaining().is_some() {
+                continue;
+            }
+            let key = (b.consecutive_errors, b.last_error_at);
+            if best.is_none_or(|(top, _)| key < top) {
+                best = Some((key, i));
+            }
         }
+        best.map(|(_, i)| i)
+    }
 
-        let text = resp
-            .text()
-            .await
-            .map_err(|e| RpcProxyError::BodyRead(e.to_string()))?;
+    /// Indices of the backends that may serve `request`, in priority order.
+    /// Without an explicit route, archive-tagged backends move to the front
+    /// for requests that need historical state.
+    fn candidates(&self, request: &JsonRpcRequest) -> Vec<usize> {
+        match self.routes.get(&request.method) {
+            Some(route) => {
+                let mut indices: Vec<usize> = route
+                    .iter()
+                    .copied()
+                    .filter(|&i| i < self.backends.len())
+                    .collect();
+                indices.sort_unstable();
+                indices.dedup();
+                indices
+            }
+            None => {
+                let mut indices: Vec<usize> = (0..self.backends.len()).collect();
+                if cache_policy::request_requires_archive(request) {
+                    indices.sort_by_key(|i| !self.archive_backends.contains(i));
+                }
+                indices
+            }
+        }
+    }
 
-        let rpc_response: JsonRpcResponse = serde_json::from_str(&text)?;
+    /// During the startup grace period, moves backends that no probe has
+    /// reported a block for behind those that one has, keeping priority order
+    /// otherwise. An unprobed backend may still be syncing; it remains a
+    /// failover target.
+    async fn prefer_probed(&self, mut candidates: Vec<usize>) -> Vec<usize> {
+        if self.grace_until.is_none_or(|until| Instant::now() >= until) {
+            return candidates;
+        }
+        let mut probed = Vec::with_capacity(self.backends.len());
+        for backend in &self.backends {
+            probed.push(backend.read().await.latest_block.is_some());
+        }
+        candidates.sort_by_key(|&i| !probed[i]);
+        candidates
+    }
 
-        Ok(rpc_response)
+    /// With a failback delay, moves backends that aren't `Healthy` or became
+    /// so less than the delay ago behind the settled ones, keeping priority
+    /// order otherwise, so a recovering primary doesn't take traffic back
+    /// until it has proven stable.
+    async fn prefer_settled(&self, mut candidates: Vec<usize>) -> Vec<usize> {
+        if self.failback_delay.is_zero() {
+            return candidates;
+        }
+        let mut settled = Vec::with_capacity(self.backends.len());
+        for backend in &self.backends {
+            settled.push(backend.read().await.is_settled(self.failback_delay));
+        }
+        candidates.sort_by_key(|&i| !settled[i]);
+        candidates
+    }
+
+    /// Picks the backend for an affinity key by rendezvous hashing over the
+    /// candidates that aren't down, so a key keeps its backend as long as that
+    /// backend is usable and only moves when it goes down.
+    async fn affinity_backend(&self, key: &str, candidates: &[usize]) -> Option<usize> {
+        let mut best: Option<(u64, usize)> = None;
+        for &i in candidates {
+            let b = self.backends[i].read().await;
+            if b.state == BackendState::Down {
+                continue;
+            }
+            let mut hasher = DefaultHasher::new();
+            (key, &b.url).hash(&mut hasher);
+            let score = hasher.finish();
+            if best.is_none_or(|(top, _)| score > top) {
+                best = Some((score, i));
+            }
+        }
+        best.map(|(_, i)| i)
+    }
+
+    /// Copies read-only requests to the mirror, if one is configured.
+    /// Write methods are never mirrored so transactions aren't submitted twice.
+    fn mirror_request(&self, request: &JsonRpcRequest, response: &JsonRpcResponse) {
+        if let Some(mirror) = &self.mirror
+            && cache_policy::should_cache(&request.method)
+        {
+            mirror.shadow(&self.client, request, response);
+        }
+    }
+
+    /// Mirror comparison counters, if mirroring is enabled.
+    pub fn mirror_info(&self) -> Option<MirrorInfo> {
+        self.mirror.as_ref().map(Mirror::info)
+    }
+
+    /// The request timeout configured for `method`, if it has its own.
+    fn timeout_for(&self, method: &str) -> Option<Duration> {
+        self.method_timeouts.get(method).copied()
+    }
+
+    /// Backend URLs in priority order, with credentials hidden unless
+    /// `--show-full-urls` is set, as in `/status`.
+    pub fn backend_urls(&self) -> Vec<String> {
+        self.backend_urls.clone()
     }
 
     pub async fn backend_statuses(&self) -> Vec<BackendHealthInfo> {
@@ -124,13 +920,22 @@ impl UpstreamManager {
         for (i, backend_lock) in self.backends.iter().enumerate() {
             let b = backend_lock.read().await;
             statuses.push(BackendHealthInfo {
-                url: b.url.clone(),
+                url: b.display_url.clone(),
                 priority: i,
                 state: format!("{:?}", b.state),
+                tags: b.tags.clone(),
                 latency_ms: b.avg_latency_ms,
                 latest_block: b.latest_block,
                 total_requests: b.total_requests,
                 total_errors: b.total_errors,
+                cooldown_remaining_secs: b
+                    .cooldown_remaining()
+                    .map(|d| d.as_secs_f64().ceil() as u64),
+                down_for_secs: b.down_since.map(|at| at.elapsed().as_secs()),
+                syncing: b.syncing,
+                total_response_bytes: b.total_response_bytes,
+                avg_response_bytes: b.avg_response_bytes(),
+                slow_query_count: b.slow_queries,
                 uptime_secs: b.started_at.elapsed().as_secs(),
             });
         }
@@ -140,34 +945,145 @@ impl UpstreamManager {
     pub async fn has_healthy_backend_with_block(&self) -> bool {
         for backend_lock in &self.backends {
             let b = backend_lock.read().await;
-            if b.state == BackendState::Healthy && b.latest_block.is_some() {
+            if b.state == BackendState::Healthy && b.has_fresh_block(self.block_freshness) {
                 return true;
             }
         }
         false
     }
 
+    /// Number of backends that are healthy and have reported a block number,
+    /// recently enough if a block freshness window is set.
+    pub async fn healthy_backend_count(&self) -> usize {
+        let mut count = 0;
+        for backend_lock in &self.backends {
+            let b = backend_lock.read().await;
+            if b.state == BackendState::Healthy && b.has_fresh_block(self.block_freshness) {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Highest block number reported by a healthy backend.
+    pub async fn latest_block(&self) -> Option<u64> {
+        let mut latest = None;
+        for backend_lock in &self.backends {
+            let b = backend_lock.read().await;
+            if b.state == BackendState::Healthy {
+                latest = latest.max(b.latest_block);
+            }
+        }
+        latest
+    }
+
+    /// Highest block number a healthy backend reported within `window`.
+    pub async fn latest_block_seen_within(&self, window: Duration) -> Option<u64> {
+        let mut latest = None;
+        for backend_lock in &self.backends {
+            let b = backend_lock.read().await;
+            if b.state == BackendState::Healthy && b.has_fresh_block(Some(window)) {
+                latest = latest.max(b.latest_block);
+            }
+        }
+        latest
+    }
+
+    /// How long ago the most recently advancing backend saw its block change,
+    /// or `None` if no backend has reported a block yet.
+    pub async fn freshest_block_age(&self) -> Option<Duration> {
+        let mut freshest: Option<Duration> = None;
+        for backend_lock in &self.backends {
+            let b = backend_lock.read().await;
+            if let Some(at) = b.latest_block_at {
+                let age = at.elapsed();
+                freshest = Some(freshest.map_or(age, |f| f.min(age)));
+            }
+        }
+        freshest
+    }
+
+    /// The proxy upstream connections go through, for clients built outside
+    /// the manager such as the health prober's.
+    pub fn upstream_proxy(&self) -> Option<&reqwest::Proxy> {
+        self.upstream_proxy.as_ref()
+    }
+
+    /// Whether health checks should probe `eth_syncing`.
+    pub fn checks_syncing(&self) -> bool {
+        self.check_syncing
+    }
+
     /// Returns a handle to the notify used to trigger reactive health checks.
     pub fn health_notify(&self) -> Arc<Notify> {
         self.health_notify.clone()
     }
 
+    /// Records whether each backend reports itself syncing. A backend that
+    /// is syncing drops to `Degraded` now and stays there on later successes
+    /// until it reports done. A failed probe changes nothing, so nodes
+    /// without `eth_syncing` aren't penalized.
+    pub async fn check_syncing<F, Fut>(&self, probe: F)
+    where
+        F: Fn(Target) -> Fut,
+        Fut: std::future::Future<Output = Result<bool, RpcProxyError>>,
+    {
+        for backend_lock in &self.backends {
+            let (target, url) = {
+                let backend = backend_lock.read().await;
+                (backend.target(), backend.display_url.clone())
+            };
+            match probe(target).await {
+                Ok(syncing) => {
+                    let mut backend = backend_lock.write().await;
+                    if syncing && !backend.syncing {
+                        warn!(backend = %url, "backend is syncing, marking degraded");
+                    } else if !syncing && backend.syncing {
+                        info!(backend = %url, "backend finished syncing");
+                    }
+                    backend.syncing = syncing;
+                    if syncing && backend.state == BackendState::Healthy {
+                        backend.state = BackendState::Degraded;
+                        self.note_transition(
+                            &backend,
+                            StateTransition::Changed {
+                                from: BackendState::Healthy,
+                                to: BackendState::Degraded,
+                            },
+                        );
+                    }
+                }
+                Err(e) => {
+                    debug!(backend = %url, error = %e, "eth_syncing probe failed");
+                }
+            }
+        }
+    }
+
     /// Runs a health probe on each backend and updates their state.
     /// Used by the health checker — keeps backend mutation encapsulated.
     pub async fn check_all_backends<F, Fut>(&self, probe: F)
     where
-        F: Fn(String) -> Fut,
+        F: Fn(Target) -> Fut,
         Fut: std::future::Future<Output = Result<u64, RpcProxyError>>,
     {
         let mut best_block: Option<u64> = None;
 
         for backend_lock in &self.backends {
-            let url = backend_lock.read().await.url.clone();
-            match probe(url.clone()).await {
+            let (target, url) = {
+                let backend = backend_lock.read().await;
+                (backend.target(), backend.display_url.clone())
+            };
+            match probe(target).await {
                 Ok(block_number) => {
                     let mut backend = backend_lock.write().await;
-                    backend.latest_block = Some(block_number);
+                    let old_state = backend.state;
+                    backend.record_block(block_number);
                     backend.record_success(0.0);
+                    self.note_transition(
+                        &backend,
+                        StateTransition::between(old_state, backend.state),
+                    );
                     debug!(backend = %url, block = %block_number, "health check passed");
 
                     match best_block {
@@ -178,7 +1094,8 @@ impl UpstreamManager {
                 }
                 Err(e) => {
                     let mut backend = backend_lock.write().await;
-                    backend.record_error();
+                    let transition = backend.record_error();
+                    self.note_transition(&backend, transition);
                     warn!(backend = %url, error = %e, state = ?backend.state, "health check failed");
                 }
             }
@@ -194,8 +1111,15 @@ impl UpstreamManager {
                     && backend.state == BackendState::Healthy
                 {
                     backend.state = BackendState::Degraded;
+                    self.note_transition(
+                        &backend,
+                        StateTransition::Changed {
+                            from: BackendState::Healthy,
+                            to: BackendState::Degraded,
+                        },
+                    );
                     warn!(
-                        backend = %backend.url,
+                        backend = %backend.display_url,
                         block = %block,
                         best_block = %best,
                         "backend is stale, marking degraded"
@@ -205,3 +1129,365 @@ impl UpstreamManager {
         }
     }
 }
+
+/// Whether two backends gave the same answer: the same result, or errors
+/// with the same code.
+pub(super) fn same_answer(a: &JsonRpcResponse, b: &JsonRpcResponse) -> bool {
+    a.result == b.result && a.error.as_ref().map(|e| e.code) == b.error.as_ref().map(|e| e.code)
+}
+
+tokio::task_local! {
+    /// Display URL of the backend that last answered within `track_backend`.
+    static SERVED_BY: RefCell<Option<String>>;
+}
+
+/// Runs `fut` and also returns the display URL of the last backend that
+/// answered a call made within it, if any. Calls made from tasks it spawns
+/// aren't seen.
+pub async fn track_backend<F: Future>(fut: F) -> (F::Output, Option<String>) {
+    SERVED_BY
+        .scope(RefCell::new(None), async {
+            let output = fut.await;
+            (output, SERVED_BY.with(|b| b.take()))
+        })
+        .await
+}
+
+fn note_served_by(backend: &str) {
+    let _ = SERVED_BY.try_with(|b| *b.borrow_mut() = Some(backend.to_string()));
+}
+
+tokio::task_local! {
+    /// Backend tried first by calls within `pin_backend`, once one answered.
+    static PINNED: Cell<Option<usize>>;
+}
+
+/// Runs `fut` with its upstream calls pinned to one backend: the first that
+/// answers one of them is tried first by all that follow, so they see the
+/// same chain head. Failover still applies if it stops answering. Calls made
+/// from tasks it spawns aren't pinned.
+pub async fn pin_backend<F: Future>(fut: F) -> F::Output {
+    PINNED.scope(Cell::new(None), fut).await
+}
+
+fn note_pin(backend: usize) {
+    let _ = PINNED.try_with(|p| {
+        if p.get().is_none() {
+            p.set(Some(backend));
+        }
+    });
+}
+
+/// A random delay between zero and `max`, inclusive, spreading out retries
+/// that would otherwise fire together.
+fn retry_jitter(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    Duration::from_nanos(rand::random_range(0..=max.as_nanos() as u64))
+}
+
+/// Runs `fut` unless `deadline` passes first, in which case returns `None`.
+async fn within<F: Future>(deadline: Option<Instant>, fut: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            tokio::time::timeout(remaining, fut).await.ok()
+        }
+        None => Some(fut.await),
+    }
+}
+
+/// Sends one request to one backend and validates the response.
+/// Also returns the size of the response body in bytes.
+///
+/// If the connection drops while the body is being read, read-only methods
+/// are retried once on the same backend before failing: that is usually a
+/// keep-alive race rather than a backend problem. Write methods are never
+/// resent.
+/// `timeout` replaces the client's request timeout, and `max_bytes` bounds
+/// how much of the body is read before giving up.
+pub(super) async fn forward_to_backend(
+    client: &Client,
+    target: &Target,
+    request: &JsonRpcRequest,
+    timeout: Option<Duration>,
+    max_bytes: Option<usize>,
+) -> Result<(JsonRpcResponse, usize), RpcProxyError> {
+    let resp = post_to_backend(client, target, request, timeout).await?;
+
+    let text = match read_body(resp, max_bytes).await {
+        Ok(text) => text,
+        Err(RpcProxyError::BodyRead(e)) if cache_policy::should_cache(&request.method) => {
+            debug!(error = %e, method = %request.method, "upstream body read failed, retrying once");
+            let resp = post_to_backend(client, target, request, timeout).await?;
+            read_body(resp, max_bytes).await?
+        }
+        Err(e) => return Err(e),
+    };
+
+    let rpc_response = parse_response(&text, request)?;
+    Ok((rpc_response, text.len()))
+}
+
+/// Sends a batch to one backend. A body that isn't one valid response per
+/// call, matched by id, comes back as `None` rather than an error: the
+/// backend itself answered fine and shouldn't be counted as failing.
+async fn forward_batch(
+    client: &Client,
+    target: &Target,
+    requests: &[JsonRpcRequest],
+    timeout: Option<Duration>,
+    max_bytes: Option<usize>,
+) -> Result<(Option<Vec<JsonRpcResponse>>, usize), RpcProxyError> {
+    let resp = post_to_backend(client, target, requests, timeout).await?;
+    let text = read_body(resp, max_bytes).await?;
+    Ok((parse_batch(&text, requests), text.len()))
+}
+
+/// Matches a batch response body to `requests` by id, in request order.
+fn parse_batch(text: &str, requests: &[JsonRpcRequest]) -> Option<Vec<JsonRpcResponse>> {
+    let values: Vec<serde_json::Value> = serde_json::from_str(text).ok()?;
+    if values.len() != requests.len() {
+        return None;
+    }
+    let mut responses = Vec::with_capacity(values.len());
+    for value in values {
+        // As in `parse_response`, `"result": null` is a valid answer.
+        if value.get("result").is_none() && value.get("error").is_none() {
+            return None;
+        }
+        responses.push(Some(serde_json::from_value::<JsonRpcResponse>(value).ok()?));
+    }
+    requests
+        .iter()
+        .map(|request| {
+            responses
+                .iter_mut()
+                .find(|r| r.as_ref().is_some_and(|r| r.id == request.id))?
+                .take()
+        })
+        .collect()
+}
+
+/// Reads the whole body chunk by chunk, failing with `ResponseTooLarge` as
+/// soon as it passes `max_bytes` rather than buffering all of it.
+async fn read_body(
+    mut resp: reqwest::Response,
+    max_bytes: Option<usize>,
+) -> Result<String, RpcProxyError> {
+    let body_read = |e: reqwest::Error| RpcProxyError::BodyRead(e.without_url().to_string());
+    let too_large = |len: usize| max_bytes.filter(|&limit| len > limit);
+
+    if let Some(limit) = resp
+        .content_length()
+        .and_then(|len| too_large(len as usize))
+    {
+        return Err(RpcProxyError::ResponseTooLarge { limit });
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await.map_err(body_read)? {
+        body.extend_from_slice(&chunk);
+        if let Some(limit) = too_large(body.len()) {
+            return Err(RpcProxyError::ResponseTooLarge { limit });
+        }
+    }
+    Ok(String::from_utf8(body)
+        .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
+}
+
+/// Like `forward_to_backend`, but once more than `threshold` bytes of body
+/// have arrived the rest is passed through as a stream without parsing. The
+/// buffered prefix must open a JSON object; anything else counts as a
+/// malformed response so the caller can fail over.
+async fn forward_streaming(
+    client: &Client,
+    target: &Target,
+    request: &JsonRpcRequest,
+    threshold: usize,
+    timeout: Option<Duration>,
+    max_bytes: Option<usize>,
+) -> Result<(UpstreamReply, usize), RpcProxyError> {
+    let mut resp = post_to_backend(client, target, request, timeout).await?;
+    let body_read = |e: reqwest::Error| RpcProxyError::BodyRead(e.without_url().to_string());
+
+    let mut buffered = Vec::new();
+    while let Some(chunk) = resp.chunk().await.map_err(body_read)? {
+        buffered.extend_from_slice(&chunk);
+        if let Some(limit) = max_bytes.filter(|&limit| buffered.len() > limit) {
+            return Err(RpcProxyError::ResponseTooLarge { limit });
+        }
+        if buffered.len() > threshold {
+            if buffered.trim_ascii_start().first() != Some(&b'{') {
+                return Err(RpcProxyError::MalformedResponse(format!(
+                    "expected a JSON object; body: {}",
+                    body_snippet(&String::from_utf8_lossy(&buffered))
+                )));
+            }
+            let size = resp
+                .content_length()
+                .map_or(buffered.len(), |rest| buffered.len() + rest as usize);
+            let prefix = stream::once(async move { Ok(Bytes::from(buffered)) });
+            let stream: ByteStream = Box::pin(prefix.chain(resp.bytes_stream()));
+            return Ok((UpstreamReply::Stream(stream), size));
+        }
+    }
+
+    let text = String::from_utf8(buffered).map_err(|e| {
+        RpcProxyError::MalformedResponse(format!("response body is not UTF-8: {e}"))
+    })?;
+    let rpc_response = parse_response(&text, request)?;
+    Ok((UpstreamReply::Parsed(rpc_response), text.len()))
+}
+
+/// Drops targets repeating an earlier one's URL and headers, keeping the
+/// first occurrence's priority and merging the duplicates' tags into it.
+/// Returns the remaining targets and, for each original position, the
+/// position it now has.
+fn dedup_targets(targets: Vec<Target>, show_full_urls: bool) -> (Vec<Target>, Vec<usize>) {
+    let mut kept: Vec<Target> = Vec::with_capacity(targets.len());
+    let mut positions = Vec::with_capacity(targets.len());
+    for target in targets {
+        match kept.iter().position(|k| {
+            k.url == target.url && k.headers == target.headers && k.basic_auth == target.basic_auth
+        }) {
+            Some(i) => {
+                let url = if show_full_urls {
+                    target.url.clone()
+                } else {
+                    sanitize_url(&target.url)
+                };
+                warn!(target = %url, "duplicate target ignored");
+                for tag in target.tags {
+                    if !kept[i].tags.contains(&tag) {
+                        kept[i].tags.push(tag);
+                    }
+                }
+                positions.push(i);
+            }
+            None => {
+                positions.push(kept.len());
+                kept.push(target);
+            }
+        }
+    }
+    (kept, positions)
+}
+
+/// POSTs `request` (one call or a batch) to a backend and checks the HTTP
+/// status. A `timeout` overrides the client's request timeout.
+async fn post_to_backend(
+    client: &Client,
+    target: &Target,
+    request: &(impl Serialize + ?Sized),
+    timeout: Option<Duration>,
+) -> Result<reqwest::Response, RpcProxyError> {
+    let body = serde_json::to_string(request)?;
+
+    let mut builder = client
+        .post(&target.url)
+        .header("content-type", "application/json");
+    for h in &target.headers {
+        builder = builder.header(&h.name, &h.value);
+    }
+    if let Some(auth) = &target.basic_auth {
+        builder = auth.apply(builder);
+    }
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+
+    let resp = builder
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| RpcProxyError::UpstreamRequest(e.without_url().to_string()))?;
+
+    if resp.status() == StatusCode::TOO_MANY_REQUESTS {
+        return Err(RpcProxyError::RateLimited {
+            retry_after: retry_after(&resp),
+        });
+    }
+    if !resp.status().is_success() {
+        return Err(RpcProxyError::UpstreamHttp(resp.status().as_u16()));
+    }
+    if let Some(content_type) = non_json_content_type(&resp) {
+        return Err(RpcProxyError::NotJson {
+            status: resp.status().as_u16(),
+            content_type,
+        });
+    }
+    Ok(resp)
+}
+
+/// The response's `Content-Type` if it declares something other than JSON,
+/// such as a load balancer's HTML error page. A missing header passes, as
+/// does `text/plain`, which some nodes send with JSON bodies.
+fn non_json_content_type(resp: &reqwest::Response) -> Option<String> {
+    let value = resp.headers().get(reqwest::header::CONTENT_TYPE)?;
+    let value = String::from_utf8_lossy(value.as_bytes());
+    let essence = value.split(';').next().unwrap_or_default().trim();
+    let json = essence.is_empty()
+        || essence.to_ascii_lowercase().contains("json")
+        || essence.eq_ignore_ascii_case("text/plain");
+    (!json).then(|| value.into_owned())
+}
+
+/// The `Retry-After` delay of a 429, if given in seconds. The HTTP-date form
+/// is rare from RPC providers and is ignored.
+fn retry_after(resp: &reqwest::Response) -> Option<Duration> {
+    resp.headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Parses and validates a buffered backend response body.
+fn parse_response(text: &str, request: &JsonRpcRequest) -> Result<JsonRpcResponse, RpcProxyError> {
+    let rpc_response: JsonRpcResponse = serde_json::from_str(text).map_err(|e| {
+        RpcProxyError::MalformedResponse(format!("{e}; body: {}", body_snippet(text)))
+    })?;
+
+    // `"result": null` deserializes to `None`, so only flag responses
+    // where the key is genuinely missing.
+    if rpc_response.result.is_none() && rpc_response.error.is_none() && !has_result_key(text) {
+        return Err(RpcProxyError::MalformedResponse(format!(
+            "neither result nor error present; body: {}",
+            body_snippet(text)
+        )));
+    }
+
+    // A response for a different call must never be served (or cached) for this one.
+    if rpc_response.id != request.id {
+        return Err(RpcProxyError::IdMismatch {
+            expected: request.id.clone(),
+            actual: rpc_response.id,
+        });
+    }
+
+    Ok(rpc_response)
+}
+
+const BODY_SNIPPET_BYTES: usize = 256;
+
+/// The first `BODY_SNIPPET_BYTES` of an upstream body, cut on a char boundary.
+fn body_snippet(text: &str) -> String {
+    if text.len() <= BODY_SNIPPET_BYTES {
+        return text.to_string();
+    }
+    let mut end = BODY_SNIPPET_BYTES;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}...", &text[..end])
+}
+
+fn has_result_key(text: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(text)
+        .map(|v| v.get("result").is_some())
+        .unwrap_or(false)
+}
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use reqwest::Client;
+use tracing::{debug, warn};
+
+use crate::jsonrpc::{JsonRpcRequest, JsonRpcResponse};
+
+use super::manager::{forward_to_backend, same_answer};
+use super::target::{Target, sanitize_url};
+
+/// Shadow backend that receives a sample of live traffic so its answers can
+/// be compared with the primary's. Clients only ever see the primary's response.
+pub(super) struct Mirror {
+    target: Target,
+    display_url: String,
+    rate: f64,
+    max_response_bytes: Option<usize>,
+    seen: AtomicU64,
+    stats: Arc<MirrorStats>,
+}
+
+#[derive(Default)]
+struct MirrorStats {
+    requests: AtomicU64,
+    divergences: AtomicU64,
+    errors: AtomicU64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MirrorInfo {
+    pub url: String,
+    pub rate: f64,
+    pub requests: u64,
+    pub divergences: u64,
+    pub errors: u64,
+}
+
+impl Mirror {
+    pub(super) fn new(
+        target: Target,
+        rate: f64,
+        max_response_bytes: Option<usize>,
+        show_full_url: bool,
+    ) -> Self {
+        let display_url = if show_full_url {
+            target.url.clone()
+        } else {
+            sanitize_url(&target.url)
+        };
+        Self {
+            target,
+            display_url,
+            rate: rate.clamp(0.0, 1.0),
+            max_response_bytes,
+            seen: AtomicU64::new(0),
+            stats: Arc::new(MirrorStats::default()),
+        }
+    }
+
+    /// Deterministic sampling: mirrors exactly `rate` of the requests seen,
+    /// spread evenly, without needing a random number generator.
+    fn sample(&self) -> bool {
+        let n = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+        ((n + 1.0) * self.rate).floor() > (n * self.rate).floor()
+    }
+
+    /// Replays `request` against the mirror in the background and records
+    /// whether its answer matches `primary`.
+    pub(super) fn shadow(
+        &self,
+        client: &Client,
+        request: &JsonRpcRequest,
+        primary: &JsonRpcResponse,
+    ) {
+        if !self.sample() {
+            return;
+        }
+
+        let client = client.clone();
+        let target = self.target.clone();
+        let display_url = self.display_url.clone();
+        let stats = self.stats.clone();
+        let request = request.clone();
+        let primary = primary.clone();
+        let max_bytes = self.max_response_bytes;
+
+        tokio::spawn(async move {
+            stats.requests.fetch_add(1, Ordering::Relaxed);
+            match forward_to_backend(&client, &target, &request, None, max_bytes).await {
+                Ok((mirrored, _)) => {
+                    if same_answer(&mirrored, &primary) {
+                        debug!(mirror = %display_url, method = %request.method, "mirror matched primary");
+                    } else {
+                        stats.divergences.fetch_add(1, Ordering::Relaxed);
+                        warn!(
+                            mirror = %display_url,
+                            method = %request.method,
+                            primary = ?primary.result,
+                            mirrored = ?mirrored.result,
+                            "mirror response diverged from primary"
+                        );
+                    }
+                }
+                Err(e) => {
+                    stats.errors.fetch_add(1, Ordering::Relaxed);
+                    warn!(mirror = %display_url, method = %request.method, error = %e, "mirror request failed");
+                }
+            }
+        });
+    }
+
+    pub(super) fn info(&self) -> MirrorInfo {
+        MirrorInfo {
+            url: self.display_url.clone(),
+            rate: self.rate,
+            requests: self.stats.requests.load(Ordering::Relaxed),
+            divergences: self.stats.divergences.load(Ordering::Relaxed),
+            errors: self.stats.errors.load(Ordering::Relaxed),
+        }
+    }
+}
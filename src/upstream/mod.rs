@@ -1,5 +1,12 @@
 mod backend;
 mod manager;
+mod mirror;
+mod target;
+mod webhook;
 
-pub use backend::{BackendState, BackendStatus};
-pub use manager::UpstreamManager;
+pub use backend::{BackendState, BackendStatus, StateTransition};
+pub use manager::{
+    ByteStream, UpstreamManager, UpstreamOptions, UpstreamReply, pin_backend, track_backend,
+};
+pub use mirror::MirrorInfo;
+pub use target::{BasicAuth, Target, TargetHeader, sanitize_url};
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::Client;
+use tracing::{debug, warn};
+
+use super::backend::BackendState;
+
+/// Posts backend state transitions to an alerting webhook. Transitions of one
+/// backend within `debounce` of the first are coalesced into a single event
+/// from the state it started in to the state it ended in, so a flapping
+/// backend doesn't page repeatedly; if it ends where it started, nothing is sent.
+pub(super) struct StateWebhook {
+    /// Its own client: the upstream one may speak HTTP/2 only, go through
+    /// the upstream proxy, or time out sooner than an alerting endpoint needs.
+    client: Client,
+    url: String,
+    debounce: Duration,
+    /// Transitions waiting out the debounce window, by backend display URL.
+    pending: Arc<Mutex<HashMap<String, Transition>>>,
+}
+
+#[derive(Clone, Copy)]
+struct Transition {
+    from: BackendState,
+    to: BackendState,
+}
+
+impl StateWebhook {
+    pub(super) fn new(url: String, debounce: Duration) -> Self {
+        Self {
+            client: Client::new(),
+            url,
+            debounce,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Records that `backend` moved from `from` to `to`. The first transition
+    /// opens a debounce window; later ones within it only update the end state.
+    pub(super) fn notify(&self, backend: &str, from: BackendState, to: BackendState) {
+        {
+            let mut pending = self.pending.lock().expect("webhook lock poisoned");
+            if let Some(transition) = pending.get_mut(backend) {
+                transition.to = to;
+                return;
+            }
+            pending.insert(backend.to_string(), Transition { from, to });
+        }
+
+        let client = self.client.clone();
+        let url = self.url.clone();
+        let debounce = self.debounce;
+        let pending = self.pending.clone();
+        let backend = backend.to_string();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(debounce).await;
+            let Some(transition) = pending
+                .lock()
+                .expect("webhook lock poisoned")
+                .remove(&backend)
+            else {
+                return;
+            };
+            if transition.from == transition.to {
+                debug!(backend = %backend, state = ?transition.to, "backend flapped back, no state change sent");
+                return;
+            }
+
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            let event = serde_json::json!({
+                "backend": backend,
+                "old_state": format!("{:?}", transition.from),
+                "new_state": format!("{:?}", transition.to),
+                "timestamp": timestamp,
+            });
+            match client.post(&url).json(&event).send().await {
+                Ok(resp) if resp.status().is_success() => {
+                    debug!(backend = %backend, "state change webhook delivered");
+                }
+                Ok(resp) => {
+                    warn!(backend = %backend, status = %resp.status(), "state change webhook rejected");
+                }
+                Err(e) => {
+                    warn!(backend = %backend, error = %e.without_url(), "state change webhook failed");
+                }
+            }
+        });
+    }
+}
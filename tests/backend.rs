@@ -1,4 +1,4 @@
-use rpcproxy::upstream::{BackendState, BackendStatus};
+use rpcproxy::upstream::{BackendState, BackendStatus, StateTransition};
 
 #[test]
 fn state_transitions() {
@@ -21,6 +21,54 @@ fn state_transitions() {
     assert_eq!(backend.consecutive_errors, 0);
 }
 
+/// A burst of errors reports the move to Down once, at the third error,
+/// so the reactive health check isn't woken for every failure.
+#[test]
+fn rapid_errors_report_one_down_transition() {
+    let mut backend = BackendStatus::new("http://localhost:8545".to_string());
+    let transitions: Vec<_> = (0..10).map(|_| backend.record_error()).collect();
+
+    let down: Vec<_> = transitions
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| t.went_down())
+        .map(|(i, _)| i + 1)
+        .collect();
+    assert_eq!(down, vec![3]);
+    assert_eq!(
+        transitions[0],
+        StateTransition::Changed {
+            from: BackendState::Healthy,
+            to: BackendState::Degraded
+        }
+    );
+    assert_eq!(transitions[1], StateTransition::Unchanged);
+    assert!(
+        transitions[3..]
+            .iter()
+            .all(|t| *t == StateTransition::Unchanged)
+    );
+}
+
+/// `down_since` marks the move to Down, not the latest error, and clears on
+/// recovery.
+#[test]
+fn down_since_tracks_down_period() {
+    let mut backend = BackendStatus::new("http://localhost:8545".to_string());
+    backend.record_error();
+    backend.record_error();
+    assert!(backend.down_since.is_none());
+
+    backend.record_error();
+    let since = backend.down_since.expect("set when the backend went down");
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    backend.record_error();
+    assert_eq!(backend.down_since, Some(since));
+
+    backend.record_success(50.0);
+    assert!(backend.down_since.is_none());
+}
+
 #[test]
 fn latency_tracking() {
     let mut backend = BackendStatus::new("http://localhost:8545".to_string());
@@ -31,3 +79,43 @@ fn latency_tracking() {
     // 100 * 0.8 + 200 * 0.2 = 120
     assert!((backend.avg_latency_ms - 120.0).abs() < 0.01);
 }
+
+#[test]
+fn block_timestamp_only_moves_when_block_advances() {
+    let mut backend = BackendStatus::new("http://localhost:8545".to_string());
+    assert!(backend.latest_block_at.is_none());
+
+    backend.record_block(100);
+    let first = backend.latest_block_at.unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(5));
+
+    backend.record_block(100);
+    assert_eq!(backend.latest_block_at.unwrap(), first);
+
+    backend.record_block(101);
+    assert!(backend.latest_block_at.unwrap() > first);
+    assert_eq!(backend.latest_block, Some(101));
+}
+
+#[test]
+fn response_size_tracking() {
+    let mut backend = BackendStatus::new("http://localhost:8545".to_string());
+    assert_eq!(backend.avg_response_bytes(), 0.0);
+
+    backend.record_response_bytes(100);
+    backend.record_response_bytes(300);
+    assert_eq!(backend.total_response_bytes, 400);
+    assert_eq!(backend.avg_response_bytes(), 200.0);
+}
+
+#[test]
+fn rate_limit_sets_cooldown_without_degrading() {
+    let mut backend = BackendStatus::new("http://localhost:8545".to_string());
+    backend.record_rate_limited(Some(std::time::Duration::from_secs(30)));
+    assert_eq!(backend.state, BackendState::Healthy);
+    assert_eq!(backend.consecutive_errors, 0);
+    assert!(backend.cooldown_remaining().unwrap() > std::time::Duration::from_secs(29));
+
+    backend.record_rate_limited(None);
+    assert!(backend.cooldown_remaining().is_none());
+}
@@ -1,8 +1,8 @@
 use std::sync::Arc;
 use std::time::Duration;
 
-use rpcproxy::cache::RpcCache;
-use rpcproxy::cache::policy::{self, IMMUTABLE_TTL_SECS};
+use rpcproxy::cache::policy::{self, FEE_HISTORY_TIP_TTL_MS, IMMUTABLE_TTL_SECS};
+use rpcproxy::cache::{RpcCache, hash_key};
 use rpcproxy::jsonrpc::{JsonRpcRequest, JsonRpcResponse};
 
 const IMMUTABLE_TTL: Duration = Duration::from_secs(IMMUTABLE_TTL_SECS);
@@ -20,6 +20,93 @@ fn policy_should_cache() {
     assert!(!policy::should_cache("personal_sign"));
 }
 
+#[test]
+fn policy_is_write_method() {
+    for method in [
+        "eth_sendRawTransaction",
+        "eth_sendTransaction",
+        "personal_sign",
+        "miner_start",
+        "admin_addPeer",
+    ] {
+        assert!(policy::is_write_method(method), "{method} is a write");
+    }
+    // Never cached, but not writes.
+    assert!(!policy::is_write_method("eth_newFilter"));
+    assert!(!policy::is_write_method("debug_traceTransaction"));
+    assert!(!policy::is_write_method("eth_call"));
+}
+
+#[test]
+fn policy_requires_archive() {
+    assert!(policy::requires_archive("eth_getProof"));
+    assert!(policy::requires_archive("debug_storageRangeAt"));
+    assert!(!policy::requires_archive("eth_blockNumber"));
+    assert!(!policy::requires_archive("eth_getStorageAt"));
+
+    let storage_at = |block: serde_json::Value| JsonRpcRequest {
+        jsonrpc: "2.0".to_string(),
+        method: "eth_getStorageAt".to_string(),
+        params: serde_json::json!(["0xabc", "0x0", block]),
+        id: serde_json::json!(1),
+    };
+    assert!(policy::request_requires_archive(&storage_at(
+        serde_json::json!("0x10")
+    )));
+    assert!(policy::request_requires_archive(&storage_at(
+        serde_json::json!({"blockHash": "0x01"})
+    )));
+    assert!(!policy::request_requires_archive(&storage_at(
+        serde_json::json!("latest")
+    )));
+}
+
+/// Filter methods are node-local and stateful, so they are never cached
+/// (which also keeps them out of request coalescing).
+#[test]
+fn policy_never_caches_filter_methods() {
+    for method in [
+        "eth_newFilter",
+        "eth_newBlockFilter",
+        "eth_newPendingTransactionFilter",
+        "eth_getFilterChanges",
+        "eth_getFilterLogs",
+        "eth_uninstallFilter",
+    ] {
+        assert!(!policy::should_cache(method), "{method} must not be cached");
+    }
+}
+
+#[test]
+fn policy_resolve_latest() {
+    let request = |body: &str| serde_json::from_str::<JsonRpcRequest>(body).unwrap();
+
+    let mut req = request(
+        r#"{"jsonrpc":"2.0","method":"eth_getBalance","params":["0xabc","latest"],"id":1}"#,
+    );
+    assert!(policy::resolve_latest(&mut req, 255));
+    assert_eq!(req.params, serde_json::json!(["0xabc", "0xff"]));
+
+    let mut req = request(
+        r#"{"jsonrpc":"2.0","method":"eth_getBlockByNumber","params":["latest",false],"id":1}"#,
+    );
+    assert!(policy::resolve_latest(&mut req, 16));
+    assert_eq!(req.params, serde_json::json!(["0x10", false]));
+
+    // Other tags, explicit blocks, omitted params, and archive queries stay.
+    for body in [
+        r#"{"jsonrpc":"2.0","method":"eth_getBalance","params":["0xabc","pending"],"id":1}"#,
+        r#"{"jsonrpc":"2.0","method":"eth_getBalance","params":["0xabc","0x5"],"id":1}"#,
+        r#"{"jsonrpc":"2.0","method":"eth_getBalance","params":["0xabc"],"id":1}"#,
+        r#"{"jsonrpc":"2.0","method":"eth_getStorageAt","params":["0xabc","0x0","latest"],"id":1}"#,
+        r#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":1}"#,
+    ] {
+        let mut req = request(body);
+        assert!(!policy::resolve_latest(&mut req, 16), "{body}");
+        assert_eq!(req.params, request(body).params);
+    }
+}
+
 #[test]
 fn policy_ttl_immutable_methods() {
     let default = Duration::from_millis(2000);
@@ -68,6 +155,94 @@ fn policy_ttl_block_by_number_specific() {
     assert_eq!(policy::ttl_for_request(&req, default), default);
 }
 
+#[test]
+fn policy_ttl_fee_history() {
+    let default = Duration::from_millis(2000);
+    let tip_ttl = Duration::from_millis(FEE_HISTORY_TIP_TTL_MS);
+
+    let req: JsonRpcRequest = serde_json::from_str(
+        r#"{"jsonrpc":"2.0","method":"eth_feeHistory","params":["0x4","0x10",[25,75]],"id":1}"#,
+    )
+    .unwrap();
+    assert_eq!(policy::ttl_for_request(&req, default), IMMUTABLE_TTL);
+
+    for tag in ["latest", "pending"] {
+        let req: JsonRpcRequest = serde_json::from_value(serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "eth_feeHistory",
+            "params": ["0x4", tag, [25, 75]],
+            "id": 1
+        }))
+        .unwrap();
+        assert_eq!(policy::ttl_for_request(&req, default), tip_ttl);
+        // A shorter configured TTL still wins.
+        let short = Duration::from_millis(200);
+        assert_eq!(policy::ttl_for_request(&req, short), short);
+    }
+
+    // Different reward percentiles are different cache entries.
+    let a: JsonRpcRequest = serde_json::from_str(
+        r#"{"jsonrpc":"2.0","method":"eth_feeHistory","params":["0x4","latest",[25]],"id":1}"#,
+    )
+    .unwrap();
+    let b: JsonRpcRequest = serde_json::from_str(
+        r#"{"jsonrpc":"2.0","method":"eth_feeHistory","params":["0x4","latest",[75]],"id":1}"#,
+    )
+    .unwrap();
+    assert_ne!(a.cache_key(), b.cache_key());
+}
+
+#[test]
+fn policy_gas_ttl_overrides_fee_methods() {
+    let default = Duration::from_millis(2000);
+    let gas = Duration::from_millis(500);
+    let request = |method: &str, params: serde_json::Value| -> JsonRpcRequest {
+        serde_json::from_value(serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": 1
+        }))
+        .unwrap()
+    };
+
+    for req in [
+        request("eth_gasPrice", serde_json::json!([])),
+        request("eth_maxPriorityFeePerGas", serde_json::json!([])),
+        request("eth_feeHistory", serde_json::json!(["0x4", "latest", [50]])),
+    ] {
+        assert_eq!(
+            policy::ttl_with_gas_override(&req, default, Some(gas)),
+            gas,
+            "{}",
+            req.method
+        );
+        assert_eq!(
+            policy::ttl_with_gas_override(&req, default, None),
+            policy::ttl_for_request(&req, default)
+        );
+    }
+
+    // Pinned fee history stays immutable, other methods keep their TTL.
+    let pinned = request("eth_feeHistory", serde_json::json!(["0x4", "0x10", [50]]));
+    assert_eq!(
+        policy::ttl_with_gas_override(&pinned, default, Some(gas)),
+        IMMUTABLE_TTL
+    );
+    let block = request("eth_blockNumber", serde_json::json!([]));
+    assert_eq!(
+        policy::ttl_with_gas_override(&block, default, Some(gas)),
+        default
+    );
+
+    let cache = RpcCache::new(100, 2000).with_gas_ttl(Some(gas));
+    assert_eq!(
+        cache.ttl_for(&request("eth_gasPrice", serde_json::json!([]))),
+        gas
+    );
+    assert_eq!(cache.ttl_for(&block), default);
+}
+
 #[test]
 fn policy_ttl_get_logs_with_block_hash() {
     let default = Duration::from_millis(2000);
@@ -88,10 +263,187 @@ fn policy_ttl_get_logs_with_block_hash() {
 // Store
 // ---------------------------------------------------------------------------
 
+/// A zero default TTL disables the cache for every method.
+#[test]
+fn store_zero_ttl_disables_caching() {
+    let cache = RpcCache::new(100, 0);
+    assert!(!cache.is_enabled());
+    assert!(!cache.should_cache("eth_blockNumber"));
+    assert!(!cache.should_cache("eth_chainId"));
+
+    let cache = RpcCache::new(100, 2000);
+    assert!(cache.is_enabled());
+    assert!(cache.should_cache("eth_chainId"));
+    assert!(!cache.should_cache("eth_sendRawTransaction"));
+}
+
+/// Only successes, and `-32601` errors when a TTL is set for them, are storable.
+#[test]
+fn store_method_not_found_ttl() {
+    let request = JsonRpcRequest {
+        jsonrpc: "2.0".to_string(),
+        method: "eth_chainId".to_string(),
+        params: serde_json::json!([]),
+        id: serde_json::json!(1),
+    };
+    let ok = JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        result: Some(serde_json::json!("0x1")),
+        error: None,
+        id: serde_json::json!(1),
+    };
+    let not_found = JsonRpcResponse::error(serde_json::json!(1), -32601, "Method not found");
+    let other = JsonRpcResponse::error(serde_json::json!(1), -32000, "header not found");
+
+    let cache = RpcCache::new(100, 2000);
+    assert_eq!(cache.storable_ttl(&request, &ok, None), Some(IMMUTABLE_TTL));
+    assert_eq!(cache.storable_ttl(&request, &not_found, None), None);
+
+    let cache = cache.with_method_not_found_ttl(Some(Duration::from_secs(30)));
+    assert_eq!(
+        cache.storable_ttl(&request, &not_found, None),
+        Some(Duration::from_secs(30))
+    );
+    assert_eq!(cache.storable_ttl(&request, &other, None), None);
+}
+
+/// A block past the head will exist soon, so its `null` isn't kept for an hour.
+#[test]
+fn store_future_block_gets_short_ttl() {
+    let default = Duration::from_millis(2000);
+    let block = |number: &str| JsonRpcRequest {
+        jsonrpc: "2.0".to_string(),
+        method: "eth_getBlockByNumber".to_string(),
+        params: serde_json::json!([number, false]),
+        id: serde_json::json!(1),
+    };
+    let null = JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        result: Some(serde_json::Value::Null),
+        error: None,
+        id: serde_json::json!(1),
+    };
+
+    let cache = RpcCache::new(100, 2000);
+    assert_eq!(
+        cache.storable_ttl(&block("0x1000"), &null, Some(0x10)),
+        Some(default)
+    );
+    assert_eq!(
+        cache.storable_ttl(&block("0x10"), &null, Some(0x10)),
+        Some(IMMUTABLE_TTL)
+    );
+    // Without a known head the block is taken as pinned.
+    assert_eq!(
+        cache.storable_ttl(&block("0x1000"), &null, None),
+        Some(IMMUTABLE_TTL)
+    );
+
+    let cache = cache.with_future_blocks_cached(false);
+    assert_eq!(
+        cache.storable_ttl(&block("0x1000"), &null, Some(0x10)),
+        None
+    );
+    assert_eq!(
+        cache.storable_ttl(&block("0x10"), &null, Some(0x10)),
+        Some(IMMUTABLE_TTL)
+    );
+}
+
+#[test]
+fn policy_is_beyond_head() {
+    let request = |method: &str, params: serde_json::Value| JsonRpcRequest {
+        jsonrpc: "2.0".to_string(),
+        method: method.to_string(),
+        params,
+        id: serde_json::json!(1),
+    };
+    let head = Some(0x10);
+
+    assert!(policy::is_beyond_head(
+        &request("eth_getBlockByNumber", serde_json::json!(["0x11", false])),
+        head
+    ));
+    assert!(policy::is_beyond_head(
+        &request(
+            "eth_getBalance",
+            serde_json::json!(["0xabc", {"blockNumber": "0x20"}])
+        ),
+        head
+    ));
+    assert!(policy::is_beyond_head(
+        &request("eth_feeHistory", serde_json::json!(["0x4", "0x11", []])),
+        head
+    ));
+    assert!(!policy::is_beyond_head(
+        &request("eth_getBlockByNumber", serde_json::json!(["0x10", false])),
+        head
+    ));
+    assert!(!policy::is_beyond_head(
+        &request("eth_getBlockByNumber", serde_json::json!(["latest", false])),
+        head
+    ));
+    assert!(!policy::is_beyond_head(
+        &request("eth_chainId", serde_json::json!([])),
+        head
+    ));
+}
+
+/// Lookups are counted as hits or misses, across clones of the cache.
+#[tokio::test]
+async fn store_counts_hits_and_misses() {
+    let cache = RpcCache::new(100, 2000);
+    let resp = Arc::new(JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        result: Some(serde_json::json!("0x1")),
+        error: None,
+        id: serde_json::json!(1),
+    });
+    assert!(cache.get_serialized("k").await.is_none());
+    cache
+        .insert("k".to_string(), resp, Duration::from_secs(60))
+        .await;
+    let clone = cache.clone();
+    assert!(clone.get_serialized("k").await.is_some());
+    assert!(clone.get_serialized("k").await.is_some());
+    assert_eq!(cache.hit_counts(), (2, 1));
+}
+
+/// `keys` pages through live entries in key order with TTL and size.
+#[tokio::test]
+async fn store_keys_paginated_in_order() {
+    let cache = RpcCache::new(100, 2000);
+    let resp = Arc::new(JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        result: Some(serde_json::json!("0x1")),
+        error: None,
+        id: serde_json::json!(1),
+    });
+    for key in ["c", "a", "b"] {
+        cache
+            .insert(key.to_string(), resp.clone(), Duration::from_secs(60))
+            .await;
+    }
+
+    let (total, page) = cache.keys(0, 2);
+    assert_eq!(total, 3);
+    let keys: Vec<_> = page.iter().map(|k| k.key.as_str()).collect();
+    assert_eq!(keys, ["a", "b"]);
+    assert!(page[0].ttl_remaining_ms <= 60_000 && page[0].ttl_remaining_ms > 50_000);
+    assert_eq!(
+        page[0].size_bytes,
+        br#"{"jsonrpc":"2.0","result":"0x1","id":}"#.len()
+    );
+
+    let (_, page) = cache.keys(2, 2);
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].key, "c");
+}
+
 #[tokio::test]
 async fn store_get_miss() {
     let cache = RpcCache::new(100, 2000);
-    assert!(cache.get("nonexistent").await.is_none());
+    assert!(!cache.contains_key("nonexistent"));
 }
 
 #[tokio::test]
@@ -106,7 +458,179 @@ async fn store_insert_and_get() {
     cache
         .insert("key1".to_string(), resp.clone(), Duration::from_secs(60))
         .await;
-    let cached = cache.get("key1").await;
-    assert!(cached.is_some());
-    assert_eq!(cached.unwrap().result, resp.result);
+    let hit = cache.get_serialized("key1").await.unwrap();
+    let mut body = Vec::new();
+    hit.body.write_with_id(&serde_json::json!(7), &mut body);
+    let cached: JsonRpcResponse = serde_json::from_slice(&body).unwrap();
+    assert_eq!(cached.result, resp.result);
+    assert_eq!(cached.id, 7);
+}
+
+/// Hashed keys are fixed-size, equal for identical cache keys and distinct
+/// for different params.
+#[tokio::test]
+async fn store_hashed_keys_isolate_params() {
+    let a = JsonRpcRequest {
+        jsonrpc: "2.0".to_string(),
+        method: "eth_getLogs".to_string(),
+        params: serde_json::json!([{"fromBlock": "0x1", "toBlock": "0x2"}]),
+        id: serde_json::json!(1),
+    };
+    let mut same = a.clone();
+    same.id = serde_json::json!(2);
+    let mut other = a.clone();
+    other.params = serde_json::json!([{"fromBlock": "0x1", "toBlock": "0x3"}]);
+
+    assert_eq!(hash_key(&a.cache_key()).len(), 64);
+    assert_eq!(hash_key(&a.cache_key()), hash_key(&same.cache_key()));
+    assert_ne!(hash_key(&a.cache_key()), hash_key(&other.cache_key()));
+
+    let cache = RpcCache::new(100, 2000).with_hashed_keys(true);
+    let resp = Arc::new(JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        result: Some(serde_json::json!([])),
+        error: None,
+        id: serde_json::json!(1),
+    });
+    cache
+        .insert(a.cache_key(), resp, Duration::from_secs(60))
+        .await;
+    assert!(cache.contains_key(&same.cache_key()));
+    let hit = cache.get_serialized(&same.cache_key()).await.unwrap();
+    assert_eq!(hit.content_hash, hit.body.content_hash());
+    assert!(!cache.contains_key(&other.cache_key()));
+
+    cache.register_inflight(&other.cache_key()).await;
+    assert!(cache.subscribe_inflight(&other.cache_key()).await.is_some());
+    cache.remove_inflight(&other.cache_key()).await;
+    assert!(cache.subscribe_inflight(&other.cache_key()).await.is_none());
+}
+
+/// Namespaced views of one store don't see each other's entries.
+#[tokio::test]
+async fn store_namespaces_are_isolated() {
+    let shared = RpcCache::new(100, 2000);
+    let gnosis = shared.clone().with_namespace(Some("gnosis".into()));
+    let mainnet = shared.clone().with_namespace(Some("mainnet".into()));
+    let resp = Arc::new(JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        result: Some(serde_json::json!("0x64")),
+        error: None,
+        id: serde_json::json!(1),
+    });
+
+    gnosis
+        .insert(
+            "eth_chainId:[]".to_string(),
+            resp.clone(),
+            Duration::from_secs(60),
+        )
+        .await;
+    assert!(gnosis.contains_key("eth_chainId:[]"));
+    assert!(!mainnet.contains_key("eth_chainId:[]"));
+    assert!(!shared.contains_key("eth_chainId:[]"));
+
+    let hashed = shared
+        .clone()
+        .with_namespace(Some("gnosis".into()))
+        .with_hashed_keys(true);
+    assert!(!hashed.contains_key("eth_chainId:[]"));
+    hashed
+        .insert("eth_chainId:[]".to_string(), resp, Duration::from_secs(60))
+        .await;
+    assert!(shared.contains_key(&hash_key("gnosis:eth_chainId:[]")));
+}
+
+// ---------------------------------------------------------------------------
+// In-flight coalescing
+// ---------------------------------------------------------------------------
+
+/// Waiters subscribed to an in-flight key receive the response sent for it.
+#[tokio::test]
+async fn inflight_subscribers_receive_response() {
+    let cache = RpcCache::new(100, 2000);
+    assert!(cache.subscribe_inflight("key").await.is_none());
+
+    let (tx, leader) = cache.register_inflight("key").await;
+    assert!(leader);
+    let mut rx = cache.subscribe_inflight("key").await.unwrap();
+    let resp = Arc::new(JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        result: Some(serde_json::json!("0x1")),
+        error: None,
+        id: serde_json::json!(1),
+    });
+    tx.send(resp).unwrap();
+    assert_eq!(
+        rx.recv().await.unwrap().result,
+        Some(serde_json::json!("0x1"))
+    );
+
+    cache.remove_inflight("key").await;
+    assert!(cache.subscribe_inflight("key").await.is_none());
+}
+
+/// A second caller registering a key already in flight joins the first call
+/// instead of replacing its sender.
+#[tokio::test]
+async fn inflight_second_register_joins_first() {
+    let cache = RpcCache::new(100, 2000);
+    let (tx, leader) = cache.register_inflight("key").await;
+    assert!(leader);
+
+    let (joined, leader) = cache.register_inflight("key").await;
+    assert!(!leader);
+    let mut rx = joined.subscribe();
+    drop(joined);
+    tx.send(Arc::new(JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        result: Some(serde_json::json!("0x1")),
+        error: None,
+        id: serde_json::json!(1),
+    }))
+    .unwrap();
+    assert_eq!(
+        rx.recv().await.unwrap().result,
+        Some(serde_json::json!("0x1"))
+    );
+}
+
+/// 1000 concurrent distinct keys register, subscribe, and complete without
+/// cross-talk or stalling on a shared lock.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn inflight_many_distinct_keys_concurrently() {
+    let cache = RpcCache::new(100, 2000);
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for i in 0..1000u64 {
+        let cache = cache.clone();
+        tasks.spawn(async move {
+            let key = format!("eth_getBalance:[\"0x{i:x}\"]");
+            let (tx, _) = cache.register_inflight(&key).await;
+            let mut rx = cache.subscribe_inflight(&key).await.unwrap();
+            tx.send(Arc::new(JsonRpcResponse {
+                jsonrpc: "2.0".to_string(),
+                result: Some(serde_json::json!(i)),
+                error: None,
+                id: serde_json::json!(1),
+            }))
+            .unwrap();
+            let got = rx.recv().await.unwrap();
+            cache.remove_inflight(&key).await;
+            assert_eq!(got.result, Some(serde_json::json!(i)));
+        });
+    }
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while let Some(joined) = tasks.join_next().await {
+            joined.unwrap();
+        }
+    })
+    .await
+    .expect("in-flight operations stalled");
+
+    for i in 0..1000u64 {
+        let key = format!("eth_getBalance:[\"0x{i:x}\"]");
+        assert!(cache.subscribe_inflight(&key).await.is_none());
+    }
 }
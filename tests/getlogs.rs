@@ -0,0 +1,106 @@
+use rpcproxy::error::RpcProxyError;
+use rpcproxy::handler::getlogs::split_request;
+use rpcproxy::jsonrpc::JsonRpcRequest;
+
+fn get_logs(filter: serde_json::Value) -> JsonRpcRequest {
+    JsonRpcRequest {
+        jsonrpc: "2.0".to_string(),
+        method: "eth_getLogs".to_string(),
+        params: serde_json::json!([filter]),
+        id: serde_json::json!(1),
+    }
+}
+
+/// Each part's block bounds; a part without `toBlock` shows as "latest".
+fn ranges(parts: &[JsonRpcRequest]) -> Vec<(String, String)> {
+    parts
+        .iter()
+        .map(|p| {
+            let f = &p.params[0];
+            (
+                f["fromBlock"].as_str().unwrap().to_string(),
+                f["toBlock"].as_str().unwrap_or("latest").to_string(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn splits_wide_range_into_ordered_chunks() {
+    let req = get_logs(serde_json::json!({
+        "fromBlock": "0x0",
+        "toBlock": "0x18",
+        "address": "0xabc"
+    }));
+    let parts = split_request(&req, None, 10).unwrap().unwrap();
+    assert_eq!(
+        ranges(&parts),
+        vec![
+            ("0x0".into(), "0x9".into()),
+            ("0xa".into(), "0x13".into()),
+            ("0x14".into(), "0x18".into()),
+        ]
+    );
+    // Other filter fields are preserved on every sub-query.
+    assert!(parts.iter().all(|p| p.params[0]["address"] == "0xabc"));
+}
+
+#[test]
+fn range_within_limit_is_not_split() {
+    let req = get_logs(serde_json::json!({ "fromBlock": "0x0", "toBlock": "0x9" }));
+    assert!(split_request(&req, None, 10).is_none());
+}
+
+/// `latest` is split by the known head, but the last part keeps the tag so
+/// blocks past a stale head aren't missed.
+#[test]
+fn latest_resolves_to_known_head() {
+    let req = get_logs(serde_json::json!({ "fromBlock": "0x64" }));
+    let parts = split_request(&req, Some(0x70), 10).unwrap().unwrap();
+    assert_eq!(
+        ranges(&parts),
+        vec![
+            ("0x64".into(), "0x6d".into()),
+            ("0x6e".into(), "latest".into())
+        ]
+    );
+    assert!(parts[1].params[0].get("toBlock").is_none());
+
+    let tagged = get_logs(serde_json::json!({ "fromBlock": "0x64", "toBlock": "latest" }));
+    let parts = split_request(&tagged, Some(0x70), 10).unwrap().unwrap();
+    assert_eq!(parts[0].params[0]["toBlock"], "0x6d");
+    assert_eq!(parts[1].params[0]["toBlock"], "latest");
+
+    // Without a known head the request is forwarded as-is.
+    assert!(split_request(&req, None, 10).is_none());
+}
+
+/// A span needing more than 1000 sub-queries is refused without building them.
+#[test]
+fn huge_span_is_rejected() {
+    let req = get_logs(serde_json::json!({
+        "fromBlock": "0x0",
+        "toBlock": "0xffffffffffffffff"
+    }));
+    match split_request(&req, None, 10) {
+        Some(Err(RpcProxyError::RangeTooWide { max_blocks })) => assert_eq!(max_blocks, 10_000),
+        other => panic!("expected RangeTooWide, got {other:?}"),
+    }
+
+    // Exactly 1000 sub-queries is still allowed.
+    let req = get_logs(serde_json::json!({ "fromBlock": "0x0", "toBlock": "0x270f" }));
+    assert_eq!(split_request(&req, None, 10).unwrap().unwrap().len(), 1000);
+}
+
+#[test]
+fn unsplittable_filters_are_left_alone() {
+    let by_hash = get_logs(serde_json::json!({ "blockHash": "0xdead" }));
+    assert!(split_request(&by_hash, Some(1000), 10).is_none());
+
+    let pending = get_logs(serde_json::json!({ "fromBlock": "0x0", "toBlock": "pending" }));
+    assert!(split_request(&pending, Some(1000), 10).is_none());
+
+    let mut other = get_logs(serde_json::json!({ "fromBlock": "0x0", "toBlock": "0x100" }));
+    other.method = "eth_getBlockByNumber".to_string();
+    assert!(split_request(&other, Some(1000), 10).is_none());
+}
@@ -3,12 +3,12 @@ use std::time::Duration;
 
 use axum::Router;
 use axum::routing::get;
-use wiremock::matchers::method;
+use wiremock::matchers::{body_partial_json, method};
 use wiremock::{Mock, MockServer, ResponseTemplate};
 
 use rpcproxy::health;
 use rpcproxy::jsonrpc::JsonRpcRequest;
-use rpcproxy::upstream::UpstreamManager;
+use rpcproxy::upstream::{UpstreamManager, UpstreamOptions};
 
 fn rpc_request(method_name: &str) -> JsonRpcRequest {
     serde_json::from_value(serde_json::json!({
@@ -56,6 +56,91 @@ async fn notify_fires_when_backend_goes_down() {
     );
 }
 
+/// With a freshness window, a block stops counting toward readiness once no
+/// probe has reported it for that long, and counts again after the next probe.
+#[tokio::test]
+async fn stale_block_observation_stops_counting() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(block_number_response("0x100")))
+        .mount(&server)
+        .await;
+
+    let upstream = UpstreamManager::with_options(
+        vec![server.uri().into()],
+        UpstreamOptions {
+            block_freshness: Some(Duration::from_millis(200)),
+            ..Default::default()
+        },
+    );
+    upstream.check_all_backends(health::probe_backend).await;
+    assert!(upstream.has_healthy_backend_with_block().await);
+    assert_eq!(upstream.healthy_backend_count().await, 1);
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(!upstream.has_healthy_backend_with_block().await);
+    assert_eq!(upstream.healthy_backend_count().await, 0);
+
+    // Same block again: not advancing, but freshly observed.
+    upstream.check_all_backends(health::probe_backend).await;
+    assert!(upstream.has_healthy_backend_with_block().await);
+}
+
+/// Mounts an `eth_syncing` answer ahead of a block number for everything else.
+async fn mount_syncing(server: &MockServer, syncing: serde_json::Value) {
+    Mock::given(method("POST"))
+        .and(body_partial_json(
+            serde_json::json!({"method": "eth_syncing"}),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "jsonrpc": "2.0",
+            "result": syncing,
+            "id": 1
+        })))
+        .with_priority(1)
+        .mount(server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(block_number_response("0x100")))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn syncing_backend_stays_degraded_until_synced() {
+    let server = MockServer::start().await;
+    mount_syncing(
+        &server,
+        serde_json::json!({"startingBlock": "0x0", "currentBlock": "0x100", "highestBlock": "0x200"}),
+    )
+    .await;
+
+    let upstream = UpstreamManager::with_options(
+        vec![server.uri().into()],
+        UpstreamOptions {
+            check_syncing: true,
+            ..Default::default()
+        },
+    );
+    upstream.check_syncing(health::probe_syncing).await;
+    upstream.check_all_backends(health::probe_backend).await;
+
+    let status = &upstream.backend_statuses().await[0];
+    assert_eq!(status.state, "Degraded");
+    assert!(status.syncing);
+    assert_eq!(upstream.healthy_backend_count().await, 0);
+
+    server.reset().await;
+    mount_syncing(&server, serde_json::json!(false)).await;
+    upstream.check_syncing(health::probe_syncing).await;
+    upstream.check_all_backends(health::probe_backend).await;
+
+    let status = &upstream.backend_statuses().await[0];
+    assert_eq!(status.state, "Healthy");
+    assert!(!status.syncing);
+    assert_eq!(upstream.healthy_backend_count().await, 1);
+}
+
 #[tokio::test]
 async fn reactive_check_recovers_backend() {
     let server = MockServer::start().await;
@@ -72,7 +157,7 @@ async fn reactive_check_recovers_backend() {
     ));
 
     upstream
-        .check_all_backends(|url| async move { rpcproxy::health::probe_backend_url(url).await })
+        .check_all_backends(|target| async move { rpcproxy::health::probe_backend(target).await })
         .await;
 
     assert!(
@@ -103,7 +188,7 @@ async fn reactive_check_recovers_backend() {
         .await;
 
     upstream
-        .check_all_backends(|url| async move { rpcproxy::health::probe_backend_url(url).await })
+        .check_all_backends(|target| async move { rpcproxy::health::probe_backend(target).await })
         .await;
 
     assert!(
@@ -180,7 +265,10 @@ async fn checker_reacts_to_notify_signal() {
 #[test]
 fn health_check_fails_when_no_server() {
     // Use a port that is very unlikely to have anything listening
-    assert_eq!(health::run_health_check(19091), 1);
+    assert_eq!(
+        health::run_health_check("127.0.0.1", 19091, "/health", None),
+        1
+    );
 }
 
 /// run_health_check returns 0 when a server responds 200 on /health.
@@ -200,9 +288,11 @@ async fn health_check_succeeds_against_healthy_server() {
 
     tokio::time::sleep(Duration::from_millis(50)).await;
 
-    let result = tokio::task::spawn_blocking(move || health::run_health_check(port))
-        .await
-        .unwrap();
+    let result = tokio::task::spawn_blocking(move || {
+        health::run_health_check("127.0.0.1", port, "/health", None)
+    })
+    .await
+    .unwrap();
     assert_eq!(result, 0);
 }
 
@@ -225,8 +315,137 @@ async fn health_check_fails_against_unhealthy_server() {
 
     tokio::time::sleep(Duration::from_millis(50)).await;
 
-    let result = tokio::task::spawn_blocking(move || health::run_health_check(port))
-        .await
-        .unwrap();
+    let result = tokio::task::spawn_blocking(move || {
+        health::run_health_check("127.0.0.1", port, "/health", None)
+    })
+    .await
+    .unwrap();
     assert_eq!(result, 1);
 }
+
+/// `--health-endpoint readiness` forwards the token as a Bearer header and
+/// `--health-host` accepts a hostname.
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn health_check_sends_token_to_readiness() {
+    use axum::http::{HeaderMap, StatusCode};
+
+    async fn readiness_handler(headers: HeaderMap) -> StatusCode {
+        match headers.get("authorization").and_then(|v| v.to_str().ok()) {
+            Some("Bearer secret") => StatusCode::OK,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    let app = Router::new().route("/readiness", get(readiness_handler));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let (with_token, without_token) = tokio::task::spawn_blocking(move || {
+        (
+            health::run_health_check("localhost", port, "/readiness", Some("secret")),
+            health::run_health_check("localhost", port, "/readiness", None),
+        )
+    })
+    .await
+    .unwrap();
+    assert_eq!(with_token, 0);
+    assert_eq!(without_token, 1);
+}
+
+/// `--check-targets` probes go through the upstream proxy when one is set.
+#[tokio::test]
+async fn check_targets_use_upstream_proxy() {
+    let proxy = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(block_number_response("0x10")))
+        .mount(&proxy)
+        .await;
+
+    let proxy_config = rpcproxy::config::parse_upstream_proxy(&proxy.uri()).unwrap();
+    let targets = vec!["http://node.invalid:8545".to_string().into()];
+    let checks = health::check_targets(&targets, true, Some(&proxy_config)).await;
+    assert!(checks[0].is_ok());
+    assert_eq!(*checks[0].block.as_ref().unwrap(), 16);
+    assert_eq!(proxy.received_requests().await.unwrap().len(), 2);
+}
+
+/// `--check-targets` reports block and chain id per target and fails if any
+/// target is unreachable.
+#[tokio::test]
+async fn check_targets_reports_each_target() {
+    let good = MockServer::start().await;
+    let bad = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(wiremock::matchers::body_partial_json(
+            serde_json::json!({"method": "eth_blockNumber"}),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(block_number_response("0x10")))
+        .mount(&good)
+        .await;
+    Mock::given(method("POST"))
+        .and(wiremock::matchers::body_partial_json(
+            serde_json::json!({"method": "eth_chainId"}),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(block_number_response("0x64")))
+        .mount(&good)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&bad)
+        .await;
+
+    let targets = vec![good.uri().into(), bad.uri().into()];
+    let checks = health::check_targets(&targets, true, None).await;
+    assert!(checks[0].is_ok());
+    assert_eq!(*checks[0].block.as_ref().unwrap(), 16);
+    assert_eq!(*checks[0].chain_id.as_ref().unwrap(), 100);
+    assert!(!checks[1].is_ok());
+
+    let report = health::format_target_report(&checks);
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("TARGET"));
+    assert!(lines[1].contains(" ok ") && lines[1].contains("16") && lines[1].contains("100"));
+    assert!(lines[2].contains("FAIL") && lines[2].contains("upstream HTTP 503"));
+
+    assert_eq!(health::run_target_check(&targets, true, None).await, 1);
+    assert_eq!(health::run_target_check(&targets[..1], true, None).await, 0);
+}
+
+/// `--require-backend-on-start` passes if any target returns a block and
+/// otherwise fails with each target's error.
+#[tokio::test]
+async fn require_reachable_target_needs_one_backend() {
+    let good = MockServer::start().await;
+    let bad = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(block_number_response("0x10")))
+        .mount(&good)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&bad)
+        .await;
+
+    let targets = vec![bad.uri().into(), good.uri().into()];
+    assert!(
+        health::require_reachable_target(&targets, true, None)
+            .await
+            .is_ok()
+    );
+
+    let err = health::require_reachable_target(&targets[..1], true, None)
+        .await
+        .unwrap_err();
+    assert!(err.starts_with("no backend reachable"), "{err}");
+    assert!(
+        err.contains(&bad.uri()) && err.contains("upstream HTTP 503"),
+        "{err}"
+    );
+}
@@ -1,4 +1,4 @@
-use rpcproxy::jsonrpc::{JsonRpcBody, JsonRpcRequest, JsonRpcResponse};
+use rpcproxy::jsonrpc::{JsonRpcBody, JsonRpcRequest, JsonRpcResponse, SerializedResponse};
 
 #[test]
 fn parse_single_request() {
@@ -35,6 +35,18 @@ fn invalid_request_missing_method() {
     assert!(!req.is_valid());
 }
 
+#[test]
+fn missing_version_parses_but_is_invalid() {
+    let json = r#"{"method":"eth_blockNumber","params":[],"id":1}"#;
+    let req: JsonRpcRequest = serde_json::from_str(json).unwrap();
+    assert_eq!(req.jsonrpc, "");
+    assert!(!req.is_valid());
+
+    let json = r#"{"jsonrpc":"1.0","method":"eth_blockNumber","params":[],"id":1}"#;
+    let req: JsonRpcRequest = serde_json::from_str(json).unwrap();
+    assert!(!req.is_valid());
+}
+
 #[test]
 fn cache_key_ignores_id() {
     let req1: JsonRpcRequest =
@@ -47,6 +59,69 @@ fn cache_key_ignores_id() {
     assert_eq!(req1.cache_key(), req2.cache_key());
 }
 
+#[test]
+fn cache_key_treats_missing_and_null_params_as_empty() {
+    let keys: Vec<String> = [
+        r#"{"jsonrpc":"2.0","method":"eth_blockNumber","id":1}"#,
+        r#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":1}"#,
+        r#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":null,"id":1}"#,
+    ]
+    .iter()
+    .map(|json| {
+        serde_json::from_str::<JsonRpcRequest>(json)
+            .unwrap()
+            .cache_key()
+    })
+    .collect();
+    assert_eq!(keys[0], keys[1]);
+    assert_eq!(keys[1], keys[2]);
+}
+
+/// Reordered filter keys share a cache key; reordered positional params don't.
+#[test]
+fn cache_key_ignores_object_key_order() {
+    let key = |json: &str| {
+        serde_json::from_str::<JsonRpcRequest>(json)
+            .unwrap()
+            .cache_key()
+    };
+    assert_eq!(
+        key(
+            r#"{"jsonrpc":"2.0","method":"eth_getLogs","params":[{"fromBlock":"0x1","toBlock":"0x2","address":"0xa","topics":[{"b":1,"a":2}]}],"id":1}"#
+        ),
+        key(
+            r#"{"jsonrpc":"2.0","method":"eth_getLogs","params":[{"address":"0xa","topics":[{"a":2,"b":1}],"toBlock":"0x2","fromBlock":"0x1"}],"id":1}"#
+        ),
+    );
+    assert_ne!(
+        key(
+            r#"{"jsonrpc":"2.0","method":"eth_getStorageAt","params":["0xa","0x0","latest"],"id":1}"#
+        ),
+        key(
+            r#"{"jsonrpc":"2.0","method":"eth_getStorageAt","params":["0x0","0xa","latest"],"id":1}"#
+        ),
+    );
+}
+
+/// Whole numbers written as floats share a key with the integer; real
+/// fractions and other integers don't.
+#[test]
+fn cache_key_normalizes_whole_floats() {
+    let key = |params: &str| {
+        serde_json::from_str::<JsonRpcRequest>(&format!(
+            r#"{{"jsonrpc":"2.0","method":"eth_feeHistory","params":{params},"id":1}}"#
+        ))
+        .unwrap()
+        .cache_key()
+    };
+    let canonical = key(r#"[4,"0x10",[25,75]]"#);
+    assert_eq!(key(r#"[4.0,"0x10",[25.0,75]]"#), canonical);
+    assert_eq!(key(r#"[4e0,"0x10",[2.5e1,75]]"#), canonical);
+    assert_ne!(key(r#"[4.5,"0x10",[25,75]]"#), canonical);
+    assert_ne!(key(r#"[5,"0x10",[25,75]]"#), canonical);
+    assert_eq!(key("[-0.0]"), key("[0]"));
+}
+
 #[test]
 fn cache_key_different_params() {
     let req1: JsonRpcRequest = serde_json::from_str(
@@ -67,3 +142,56 @@ fn error_response_serialization() {
     assert!(json.contains("-32700"));
     assert!(json.contains("Parse error"));
 }
+
+#[test]
+fn internal_error_with_data_serialization() {
+    let resp = JsonRpcResponse::internal_error(serde_json::json!(1));
+    assert!(!serde_json::to_string(&resp).unwrap().contains("data"));
+
+    let resp = JsonRpcResponse::internal_error_with_data(
+        serde_json::json!(1),
+        serde_json::json!({"elapsed_ms": 5}),
+    );
+    let json: serde_json::Value = serde_json::to_value(&resp).unwrap();
+    assert_eq!(json["error"]["code"], -32603);
+    assert_eq!(json["error"]["data"]["elapsed_ms"], 5);
+}
+
+#[test]
+fn with_id_serializes_like_owned_response() {
+    let resp: JsonRpcResponse =
+        serde_json::from_str(r#"{"jsonrpc":"2.0","result":{"logs":[1,2,3]},"id":1}"#).unwrap();
+    let id = serde_json::json!("client-7");
+
+    let mut owned = resp.clone();
+    owned.id = id.clone();
+    assert_eq!(
+        serde_json::to_string(&resp.with_id(&id)).unwrap(),
+        serde_json::to_string(&owned).unwrap()
+    );
+
+    let err = JsonRpcResponse::error(serde_json::json!(1), -32000, "boom");
+    let mut owned = err.clone();
+    owned.id = id.clone();
+    assert_eq!(
+        serde_json::to_string(&err.with_id(&id)).unwrap(),
+        serde_json::to_string(&owned).unwrap()
+    );
+}
+
+#[test]
+fn serialized_response_writes_any_id() {
+    let resp: JsonRpcResponse =
+        serde_json::from_str(r#"{"jsonrpc":"2.0","result":["0x1",{"a":null}],"id":1}"#).unwrap();
+    let serialized = SerializedResponse::new(&resp);
+
+    for id in [
+        serde_json::json!(7),
+        serde_json::json!("req-\"quoted\""),
+        serde_json::Value::Null,
+    ] {
+        let mut out = Vec::new();
+        serialized.write_with_id(&id, &mut out);
+        assert_eq!(out, serde_json::to_vec(&resp.with_id(&id)).unwrap());
+    }
+}
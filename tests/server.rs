@@ -0,0 +1,199 @@
+use std::time::Duration;
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::routing::{get, post};
+use tower::ServiceExt;
+
+use rpcproxy::handler::active::ActiveRequests;
+use rpcproxy::server::{self, ServerOptions};
+
+async fn spawn_server(options: ServerOptions) -> u16 {
+    async fn ok_handler() -> &'static str {
+        "ok"
+    }
+
+    let app = Router::new().route("/health", get(ok_handler));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    tokio::spawn(server::serve(
+        listener,
+        app,
+        options,
+        std::future::pending(),
+    ));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    port
+}
+
+/// With HTTP/2 enabled, both h2c and HTTP/1.1 clients are served.
+#[tokio::test]
+async fn http2_enabled_serves_both_protocols() {
+    let port = spawn_server(ServerOptions {
+        http2: true,
+        keepalive_timeout: Some(Duration::from_secs(30)),
+        ..Default::default()
+    })
+    .await;
+    let url = format!("http://127.0.0.1:{port}/health");
+
+    let h2 = reqwest::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let resp = h2.get(&url).send().await.unwrap();
+    assert_eq!(resp.version(), reqwest::Version::HTTP_2);
+    assert_eq!(resp.text().await.unwrap(), "ok");
+
+    let h1 = reqwest::Client::builder().http1_only().build().unwrap();
+    let resp = h1.get(&url).send().await.unwrap();
+    assert_eq!(resp.version(), reqwest::Version::HTTP_11);
+    assert_eq!(resp.text().await.unwrap(), "ok");
+}
+
+/// By default only HTTP/1.1 is accepted.
+#[tokio::test]
+async fn http2_disabled_rejects_prior_knowledge() {
+    let port = spawn_server(ServerOptions::default()).await;
+    let url = format!("http://127.0.0.1:{port}/health");
+
+    let h1 = reqwest::Client::new();
+    assert_eq!(h1.get(&url).send().await.unwrap().status(), 200);
+
+    let h2 = reqwest::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    assert!(h2.get(&url).send().await.is_err());
+}
+
+/// On shutdown the server stops accepting connections but finishes the
+/// request in progress before returning.
+#[tokio::test]
+async fn shutdown_drains_request_in_progress() {
+    async fn slow_handler() -> &'static str {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        "done"
+    }
+
+    let app = Router::new().route("/slow", get(slow_handler));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(server::serve(
+        listener,
+        app,
+        ServerOptions::default(),
+        async move {
+            let _ = stopped.await;
+        },
+    ));
+
+    let url = format!("http://127.0.0.1:{port}/slow");
+    let request = tokio::spawn(async move { reqwest::get(&url).await?.text().await });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    stop.send(()).unwrap();
+
+    assert_eq!(request.await.unwrap().unwrap(), "done");
+    tokio::time::timeout(Duration::from_secs(2), server)
+        .await
+        .expect("serve returns once drained")
+        .unwrap();
+    let refused = reqwest::get(format!("http://127.0.0.1:{port}/slow")).await;
+    assert!(refused.is_err());
+}
+
+/// While draining, a request in progress stays counted as active and the
+/// server waits for it, however long it takes.
+#[tokio::test]
+async fn shutdown_waits_for_active_requests() {
+    let active = ActiveRequests::default();
+    let counted = active.clone();
+    let app = Router::new().route(
+        "/slow",
+        get(move || async move {
+            let _active = counted.start();
+            tokio::time::sleep(Duration::from_millis(1500)).await;
+            "done"
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let options = ServerOptions {
+        active_requests: active.clone(),
+        ..Default::default()
+    };
+    let mut server = tokio::spawn(server::serve(listener, app, options, async move {
+        let _ = stopped.await;
+    }));
+
+    tokio::spawn(reqwest::get(format!("http://127.0.0.1:{port}/slow")));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(active.count(), 1);
+    stop.send(()).unwrap();
+
+    // Still draining well past a second's worth of progress logs.
+    let early = tokio::time::timeout(Duration::from_millis(1100), &mut server).await;
+    assert!(early.is_err(), "serve returned with a request in progress");
+    assert_eq!(active.count(), 1);
+    tokio::time::timeout(Duration::from_secs(2), server)
+        .await
+        .expect("serve returns once drained")
+        .unwrap();
+    assert_eq!(active.count(), 0);
+}
+
+async fn cors_preflight(origins: &[&str], origin: &str) -> axum::response::Response {
+    let origins: Vec<String> = origins.iter().map(|o| o.to_string()).collect();
+    let app = Router::new()
+        .route("/", post(|| async { "ok" }))
+        .layer(server::cors_layer(&origins).unwrap());
+    app.oneshot(
+        Request::builder()
+            .method("OPTIONS")
+            .uri("/")
+            .header("origin", origin)
+            .header("access-control-request-method", "POST")
+            .header(
+                "access-control-request-headers",
+                "authorization,content-type",
+            )
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await
+    .unwrap()
+}
+
+/// A preflight from a listed origin is answered with the allowed methods
+/// and headers.
+#[tokio::test]
+async fn cors_preflight_allows_listed_origin() {
+    let resp = cors_preflight(&["https://app.example"], "https://app.example").await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let headers = resp.headers();
+    assert_eq!(
+        headers["access-control-allow-origin"],
+        "https://app.example"
+    );
+    let methods = headers["access-control-allow-methods"].to_str().unwrap();
+    assert!(methods.contains("POST") && methods.contains("OPTIONS"));
+    let allowed = headers["access-control-allow-headers"].to_str().unwrap();
+    assert!(allowed.contains("authorization") && allowed.contains("content-type"));
+
+    let resp = cors_preflight(&["https://app.example"], "https://evil.example").await;
+    assert!(resp.headers().get("access-control-allow-origin").is_none());
+}
+
+/// `*` allows any origin and may not be mixed with specific ones.
+#[tokio::test]
+async fn cors_wildcard_allows_any_origin() {
+    let resp = cors_preflight(&["*"], "https://anything.example").await;
+    assert_eq!(resp.headers()["access-control-allow-origin"], "*");
+
+    let mixed = ["*".to_string(), "https://app.example".to_string()];
+    assert!(server::cors_layer(&mixed).is_err());
+}
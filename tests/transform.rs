@@ -0,0 +1,57 @@
+use rpcproxy::handler::transform::{StripRule, apply_strip_rules, strip_path};
+
+fn path(p: &str) -> Vec<String> {
+    p.split('.').map(str::to_string).collect()
+}
+
+#[test]
+fn strips_top_level_field() {
+    let mut block = serde_json::json!({"number": "0x1", "transactions": ["0xa", "0xb"]});
+    strip_path(&mut block, &path("transactions"));
+    assert_eq!(block, serde_json::json!({"number": "0x1"}));
+}
+
+#[test]
+fn strips_nested_field_through_arrays() {
+    let mut receipt = serde_json::json!({
+        "status": "0x1",
+        "logs": [
+            {"address": "0x1", "data": "0xdead", "meta": {"raw": "x", "keep": 1}},
+            {"address": "0x2", "data": "0xbeef", "meta": {"raw": "y", "keep": 2}}
+        ]
+    });
+    strip_path(&mut receipt, &path("logs.meta.raw"));
+    assert_eq!(
+        receipt,
+        serde_json::json!({
+            "status": "0x1",
+            "logs": [
+                {"address": "0x1", "data": "0xdead", "meta": {"keep": 1}},
+                {"address": "0x2", "data": "0xbeef", "meta": {"keep": 2}}
+            ]
+        })
+    );
+}
+
+#[test]
+fn missing_path_is_a_no_op() {
+    let mut value = serde_json::json!({"a": {"b": 1}});
+    strip_path(&mut value, &path("a.c.d"));
+    strip_path(&mut value, &path("x"));
+    assert_eq!(value, serde_json::json!({"a": {"b": 1}}));
+}
+
+#[test]
+fn rules_only_apply_to_their_method() {
+    let rules = vec![StripRule {
+        method: "eth_getBlockByNumber".to_string(),
+        path: path("transactions"),
+    }];
+    let mut value = serde_json::json!({"transactions": []});
+
+    apply_strip_rules(&rules, "eth_getBlockByHash", &mut value);
+    assert_eq!(value, serde_json::json!({"transactions": []}));
+
+    apply_strip_rules(&rules, "eth_getBlockByNumber", &mut value);
+    assert_eq!(value, serde_json::json!({}));
+}